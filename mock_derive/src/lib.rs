@@ -30,13 +30,12 @@ extern crate syn;
 extern crate quote;
 extern crate proc_macro;
 extern crate proc_macro2;
-#[macro_use]
-extern crate lazy_static;
 
 use proc_macro::TokenStream;
 use syn::parse::{Parse, ParseStream, Result as ParseResult};
+use quote::ToTokens;
 use std::collections::HashMap;
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 macro_rules! quote_field {
     ($x:expr) => {{
@@ -66,6 +65,11 @@ macro_rules! pub_token {
 enum Mockable {
     ForeignFunctions(syn::ItemForeignMod),
     Trait(syn::ItemTrait),
+    Impl(syn::ItemImpl),
+    Use(syn::ItemUse),
+    Fn(syn::ItemFn),
+    Mod(syn::ItemMod),
+    Callback(syn::ItemType),
 }
 
 struct MockInput {
@@ -83,12 +87,242 @@ impl Parse for MockInput {
     }
 }
 
+// The arguments of the mock attribute.
+struct MockArgs {
+    // The condition under which mocks are generated in place of the item.
+    cfg: proc_macro2::TokenStream,
+    name: Option<syn::Ident>,
+    vis: Option<syn::Visibility>,
+    // Left to each kind of mock when not given, see 'MockArgs::storage'.
+    storage: Option<Storage>,
+    // How extern functions fail, either "panic", "record" or "unwind".
+    failures: Option<syn::LitStr>,
+    // The prefix of the environment variables read by an extern block that is built into
+    // a preloadable library, rather than mocked in tests.
+    preload: Option<syn::LitStr>,
+    // Supertraits without a mock of their own, which are implemented for the mock by hand.
+    skip: Vec<syn::Path>,
+}
+
+// Where the expectations of static methods, functions and extern functions are kept.
+#[derive(Clone, Copy, PartialEq)]
+enum Storage {
+    // A copy for each thread, so that tests running in parallel don't see each other's expectations.
+    ThreadLocal,
+    // A single copy shared by every thread. Tests serialize themselves by holding the lock of the mock.
+    Global,
+}
+
+impl Storage {
+    // Global expectations are shared between threads, so the closures they hold must be Send.
+    fn send_bound(self) -> proc_macro2::TokenStream {
+        match self {
+            Storage::ThreadLocal => quote! {},
+            Storage::Global => quote! { + Send },
+        }
+    }
+}
+
+impl MockArgs {
+    // Failed expectations of extern functions are recorded, rather than panicking
+    // across the FFI boundary.
+    fn records_failures(&self) -> bool {
+        self.failures.as_ref().is_some_and(|failures| failures.value() == "record")
+    }
+
+    // Extern functions are defined with the unwinding variant of the ABI of their block,
+    // so that their panics fail tests that call them from Rust.
+    fn unwinds(&self) -> bool {
+        self.failures.as_ref().is_some_and(|failures| failures.value() == "unwind")
+    }
+
+    // Mocks keep their expectations for each thread unless told otherwise.
+    fn storage(&self) -> Storage {
+        self.storage.unwrap_or(Storage::ThreadLocal)
+    }
+}
+
+impl Parse for MockArgs {
+    fn parse(input: ParseStream) -> ParseResult<Self> {
+        let metas = syn::punctuated::Punctuated::<syn::NestedMeta, Token![,]>::parse_terminated(input)?;
+        let mut cfg = quote! { test };
+        let mut name = None;
+        let mut vis = None;
+        let mut storage = None;
+        let mut failures = None;
+        let mut preload = None;
+        let mut skip = Vec::new();
+        for meta in metas {
+            match meta {
+                syn::NestedMeta::Meta(syn::Meta::NameValue(ref pair)) => {
+                    let value = match pair.lit {
+                        syn::Lit::Str(ref value) => value,
+                        _ => return Err(syn::Error::new_spanned(&pair.lit, "Expected a string, as in #[mock(name = \"FakeClock\")]")),
+                    };
+
+                    if pair.path.is_ident("feature") {
+                        cfg = quote! { any(test, feature = #value) };
+                    } else if pair.path.is_ident("name") {
+                        name = Some(value.parse().map_err(|_| syn::Error::new_spanned(value, "Expected the name of the mock"))?);
+                    } else if pair.path.is_ident("vis") {
+                        vis = Some(value.parse().map_err(|_| syn::Error::new_spanned(value, "Expected a visibility, such as \"pub(crate)\""))?);
+                    } else if pair.path.is_ident("storage") {
+                        storage = match value.value().as_str() {
+                            "thread_local" => Some(Storage::ThreadLocal),
+                            "global" => Some(Storage::Global),
+                            _ => return Err(syn::Error::new_spanned(value, "Expected a storage of \"thread_local\" or \"global\"")),
+                        };
+                    } else if pair.path.is_ident("failures") {
+                        if value.value() != "panic" && value.value() != "record" && value.value() != "unwind" {
+                            return Err(syn::Error::new_spanned(value, "Expected failures of \"panic\", \"record\" or \"unwind\""));
+                        }
+                        failures = Some(value.clone());
+                    } else if pair.path.is_ident("preload") {
+                        preload = Some(value.clone());
+                    } else {
+                        return Err(syn::Error::new_spanned(&pair.path, "Unknown mock argument. Expected one of feature, cfg, name, vis, storage, failures, preload or skip"));
+                    }
+                },
+                syn::NestedMeta::Meta(syn::Meta::Path(ref path)) if path.is_ident("preload") => {
+                    preload = Some(syn::LitStr::new("MOCK_DERIVE", path.get_ident().unwrap().span()));
+                },
+                syn::NestedMeta::Meta(syn::Meta::List(ref list)) if list.path.is_ident("cfg") => {
+                    if list.nested.len() != 1 {
+                        return Err(syn::Error::new_spanned(list, "Expected a single cfg predicate, as in #[mock(cfg(any(test, feature = \"mocks\")))]"));
+                    }
+                    let predicate = &list.nested[0];
+                    cfg = quote! { #predicate };
+                },
+                syn::NestedMeta::Meta(syn::Meta::List(ref list)) if list.path.is_ident("skip") => {
+                    for nested in list.nested.iter() {
+                        match *nested {
+                            syn::NestedMeta::Meta(syn::Meta::Path(ref path)) => skip.push(path.clone()),
+                            _ => return Err(syn::Error::new_spanned(nested, "Expected the path of a supertrait, as in #[mock(skip(Other))]")),
+                        }
+                    }
+                },
+                _ => return Err(syn::Error::new_spanned(meta, "Unknown mock argument. Expected one of feature, cfg, name, vis, storage, failures, preload or skip")),
+            }
+        }
+
+        Ok(MockArgs {
+            cfg: cfg,
+            name: name,
+            vis: vis,
+            storage: storage,
+            failures: failures,
+            preload: preload,
+            skip: skip,
+        })
+    }
+}
+
+// The input of 'mock_trait!', an impl block that restates the signatures of a trait
+// that is declared elsewhere. It may be preceded by #[mock(...)], with the arguments of
+// the mock attribute.
+struct MockTraitInput {
+    args: MockArgs,
+    vis: syn::Visibility,
+    unsafety: Option<Token![unsafe]>,
+    generics: syn::Generics,
+    trait_path: syn::Path,
+    items: Vec<syn::TraitItem>,
+}
+
+impl Parse for MockTraitInput {
+    fn parse(input: ParseStream) -> ParseResult<Self> {
+        let mut args = None;
+        for attr in input.call(syn::Attribute::parse_outer)? {
+            if !attr.path.is_ident("mock") || args.is_some() {
+                return Err(syn::Error::new_spanned(attr, "mock_trait! only takes a single #[mock(...)] attribute"));
+            }
+            args = Some(if attr.tokens.is_empty() { syn::parse2(quote! {})? } else { attr.parse_args::<MockArgs>()? });
+        }
+        let mut args = match args {
+            Some(args) => args,
+            None => syn::parse2(quote! {})?,
+        };
+        if let Some(ref failures) = args.failures {
+            return Err(syn::Error::new_spanned(failures, "Failure modes only apply to extern blocks"));
+        }
+        if let Some(ref preload) = args.preload {
+            return Err(syn::Error::new_spanned(preload, "Only extern blocks can be preloaded"));
+        }
+        if let Some(skipped) = args.skip.first() {
+            return Err(syn::Error::new_spanned(skipped, "mock_trait! does not mock supertraits"));
+        }
+
+        let vis: syn::Visibility = input.parse()?;
+        let unsafety: Option<Token![unsafe]> = input.parse()?;
+        input.parse::<Token![impl]>()?;
+        let mut generics: syn::Generics = if input.peek(Token![<]) {
+            input.parse()?
+        } else {
+            syn::Generics::default()
+        };
+
+        let trait_path: syn::Path = input.parse()?;
+        // The mock may be named either after 'for' or by the name argument. Without
+        // either, it is named after the trait, as with #[mock].
+        if input.peek(Token![for]) {
+            input.parse::<Token![for]>()?;
+            let mock_name: syn::Ident = input.parse()?;
+            if args.name.is_some() {
+                return Err(syn::Error::new_spanned(mock_name, "The mock is already named by the name argument"));
+            }
+            args.name = Some(mock_name);
+            // The mock always takes the generics of the impl, so its arguments are only
+            // allowed for readability.
+            if input.peek(Token![<]) {
+                input.parse::<syn::AngleBracketedGenericArguments>()?;
+            }
+        }
+        generics.where_clause = input.parse()?;
+
+        let content;
+        braced!(content in input);
+        let mut items = Vec::new();
+        while !content.is_empty() {
+            let item: syn::TraitItem = content.parse()?;
+            if let syn::TraitItem::Method(_) = item {
+                items.push(item);
+            } else {
+                return Err(syn::Error::new_spanned(item, "mock_trait! only supports methods"));
+            }
+        }
+
+        Ok(MockTraitInput {
+            args: args,
+            vis: vis,
+            unsafety: unsafety,
+            generics: generics,
+            trait_path: trait_path,
+            items: items,
+        })
+    }
+}
+
 struct FnArgs {
     args_with_types: proc_macro2::TokenStream,
     args_with_no_self_no_types: proc_macro2::TokenStream,
+    // The generated name and declared type of every argument other than self.
+    typed_args: Vec<(proc_macro2::TokenStream, syn::Type)>,
     mutable_status: Option<syn::token::Mut>,
     is_instance_method: bool,
     takes_self_ownership: bool,
+    borrows_self: bool,
+    self_lifetime: Option<syn::Lifetime>,
+    typed_receiver: Option<TypedReceiver>,
+}
+
+// A self receiver written with a type other than a reference, such as `self: Box<Self>`.
+enum TypedReceiver {
+    Box,
+    // `self: Pin<&Self>` or `self: Pin<&mut Self>`.
+    Pin(Option<syn::token::Mut>),
+    // `self: Rc<Self>`, `self: Arc<Self>` and other smart pointers, which cannot be
+    // turned into a pointer to the fallback.
+    Shared,
 }
 
 impl FnArgs {
@@ -96,11 +330,185 @@ impl FnArgs {
         FnArgs {
             args_with_types: quote! { },
             args_with_no_self_no_types: quote! { },
+            typed_args: Vec::new(),
             mutable_status: None,
             is_instance_method: false,
             takes_self_ownership: false,
+            borrows_self: false,
+            self_lifetime: None,
+            typed_receiver: None,
+        }
+    }
+}
+
+// How the value stored in a mock method becomes the return value of the mocked fn.
+#[derive(PartialEq)]
+enum ReturnKind {
+    Owned,
+    // The fn returns `&T` borrowed from self. Results are stored as `Box<T>` and
+    // kept alive by the mock method, so no 'static data is needed.
+    BorrowedFromSelf,
+}
+
+// Replaces elided, anonymous and method level lifetimes with 'static, so that a
+// return type can be named in the fields of the mock struct.
+struct LifetimeEraser<'a> {
+    method_lifetimes: Vec<&'a syn::Ident>,
+}
+
+impl<'a> syn::visit_mut::VisitMut for LifetimeEraser<'a> {
+    fn visit_type_reference_mut(&mut self, reference: &mut syn::TypeReference) {
+        if reference.lifetime.is_none() {
+            reference.lifetime = Some(syn::Lifetime::new("'static", proc_macro2::Span::call_site()));
+        }
+
+        syn::visit_mut::visit_type_reference_mut(self, reference);
+    }
+
+    fn visit_lifetime_mut(&mut self, lifetime: &mut syn::Lifetime) {
+        if lifetime.ident == "_" || self.method_lifetimes.contains(&&lifetime.ident) {
+            *lifetime = syn::Lifetime::new("'static", proc_macro2::Span::call_site());
+        }
+    }
+}
+
+// Replaces the generic parameters of a supertrait with the arguments given in its bound,
+// such as `T` with `usize` for `trait Derived : Base<usize>`. Associated types are replaced
+// with the bindings of the bound, and `Self` with the mock.
+struct GenericSubstitution {
+    types: HashMap<syn::Ident, syn::Type>,
+    lifetimes: HashMap<syn::Ident, syn::Lifetime>,
+    associated: HashMap<syn::Ident, syn::Type>,
+    self_type: syn::Type,
+}
+
+impl GenericSubstitution {
+    // Parameters missing from 'arguments' take their default, if they have one.
+    fn new(generics: &syn::Generics, arguments: &syn::PathArguments, self_type: &syn::Type) -> GenericSubstitution {
+        let mut lifetime_args = Vec::new();
+        let mut type_args = Vec::new();
+        let mut associated = HashMap::new();
+        if let syn::PathArguments::AngleBracketed(ref args) = *arguments {
+            for arg in args.args.iter() {
+                match *arg {
+                    syn::GenericArgument::Lifetime(ref lifetime) => lifetime_args.push(lifetime.clone()),
+                    syn::GenericArgument::Type(ref ty) => type_args.push(ty.clone()),
+                    syn::GenericArgument::Binding(ref binding) => {
+                        associated.insert(binding.ident.clone(), binding.ty.clone());
+                    },
+                    _ => {},
+                }
+            }
+        }
+
+        let mut substitution = GenericSubstitution {
+            types: HashMap::new(),
+            lifetimes: HashMap::new(),
+            associated: associated,
+            self_type: self_type.clone(),
+        };
+
+        for (def, lifetime) in generics.lifetimes().zip(lifetime_args) {
+            substitution.lifetimes.insert(def.lifetime.ident.clone(), lifetime);
+        }
+
+        let mut type_args = type_args.into_iter();
+        for param in generics.type_params() {
+            let ty = match type_args.next() {
+                Some(ty) => ty,
+                None => match param.default {
+                    Some(ref default) => {
+                        let mut default = default.clone();
+                        syn::visit_mut::VisitMut::visit_type_mut(&mut substitution, &mut default);
+                        default
+                    },
+                    None => panic!("The supertrait bound does not give an argument for the generic parameter {}", param.ident),
+                },
+            };
+
+            substitution.types.insert(param.ident.clone(), ty);
+        }
+
+        substitution
+    }
+}
+
+impl syn::visit_mut::VisitMut for GenericSubstitution {
+    fn visit_type_mut(&mut self, ty: &mut syn::Type) {
+        if let syn::Type::Path(ref type_path) = *ty {
+            if type_path.qself.is_none() {
+                let ref segments = type_path.path.segments;
+                if let Some(ident) = type_path.path.get_ident() {
+                    if ident == "Self" {
+                        *ty = self.self_type.clone();
+                        return;
+                    }
+
+                    if let Some(replacement) = self.types.get(ident) {
+                        *ty = replacement.clone();
+                        return;
+                    }
+                } else if segments.len() == 2 && segments[0].ident == "Self" {
+                    if let Some(replacement) = self.associated.get(&segments[1].ident) {
+                        *ty = replacement.clone();
+                        return;
+                    }
+                }
+            }
+        }
+
+        syn::visit_mut::visit_type_mut(self, ty);
+    }
+
+    fn visit_lifetime_mut(&mut self, lifetime: &mut syn::Lifetime) {
+        if let Some(replacement) = self.lifetimes.get(&lifetime.ident) {
+            *lifetime = replacement.clone();
+        }
+    }
+}
+
+// Substitutes the generic parameters of 'base' with 'arguments', and removes them from
+// its declaration along with its associated types. Returns the predicates of its where
+// clause, and the associated types to define in the impl of 'base' for the mock.
+fn substitute_generics(base: &mut syn::ItemTrait, arguments: &syn::PathArguments, self_type: &syn::Type) -> (Vec<syn::WherePredicate>, proc_macro2::TokenStream) {
+    let mut substitution = GenericSubstitution::new(&base.generics, arguments, self_type);
+    let mut predicates = Vec::new();
+    if let Some(where_clause) = base.generics.where_clause.take() {
+        for mut predicate in where_clause.predicates.into_iter() {
+            syn::visit_mut::VisitMut::visit_where_predicate_mut(&mut substitution, &mut predicate);
+            predicates.push(predicate);
+        }
+    }
+
+    // Bounds declared on the parameters themselves, such as `T: Clone`.
+    for param in base.generics.type_params() {
+        if !param.bounds.is_empty() {
+            let ty = substitution.types[&param.ident].clone();
+            let mut bounds = param.bounds.clone();
+            for bound in bounds.iter_mut() {
+                syn::visit_mut::VisitMut::visit_type_param_bound_mut(&mut substitution, bound);
+            }
+            predicates.push(parse_quote! { #ty: #bounds });
         }
     }
+
+    let mut associated_types = proc_macro2::TokenStream::new();
+    base.items.retain(|item| match *item {
+        syn::TraitItem::Type(ref assoc) => {
+            let ref ident = assoc.ident;
+            let ref ty = substitution.associated[ident];
+            associated_types.extend(quote! { type #ident = #ty; });
+            false
+        },
+        _ => true,
+    });
+
+    base.generics = syn::Generics::default();
+    for item in base.items.iter_mut() {
+        syn::visit_mut::VisitMut::visit_trait_item_mut(&mut substitution, item);
+    }
+
+    (predicates, associated_types)
 }
 
 struct TraitFn {
@@ -112,14 +520,10 @@ struct TraitFn {
     static_mocks_def: proc_macro2::TokenStream,
     static_method_setup: proc_macro2::TokenStream,
     static_method_impl: proc_macro2::TokenStream,
-    static_method_body: proc_macro2::TokenStream
+    static_method_body: proc_macro2::TokenStream,
+    sequence_impls: Vec<proc_macro2::TokenStream>,
 }
 
-lazy_static! {
-    static ref BOUNDS_MAP: Mutex<HashMap<String, String>> = {
-        Mutex::new(HashMap::new())
-    };
-}
 
 fn parse_block(item: &syn::Item) -> Mockable {
     match item {
@@ -129,7 +533,29 @@ fn parse_block(item: &syn::Item) -> Mockable {
         syn::Item::ForeignMod(ref fmod) => {
             Mockable::ForeignFunctions(fmod.clone())
         },
-        _ => { panic!("#[mock] must be applied to a trait declaration OR a extern block."); }
+        syn::Item::Impl(ref impl_item) if impl_item.trait_.is_none() => {
+            Mockable::Impl(impl_item.clone())
+        },
+        syn::Item::Use(ref use_item) => {
+            Mockable::Use(use_item.clone())
+        },
+        syn::Item::Fn(ref fn_item) => {
+            Mockable::Fn(fn_item.clone())
+        },
+        syn::Item::Mod(ref mod_item) => {
+            Mockable::Mod(mod_item.clone())
+        },
+        syn::Item::Type(ref type_item) => {
+            Mockable::Callback(type_item.clone())
+        },
+        _ => { panic!("#[mock] must be applied to a trait declaration, an extern block, an inherent impl block, a function, a module, a use declaration or a function pointer type."); }
+    }
+}
+
+fn is_self_pattern(pat: &syn::Pat) -> bool {
+    match *pat {
+        syn::Pat::Ident(ref pat_ident) => pat_ident.ident == "self",
+        _ => false,
     }
 }
 
@@ -141,7 +567,6 @@ fn parse_args<'a, I: Iterator<Item=&'a syn::FnArg>>(decl: I) -> FnArgs {
         match input {
             //The self argument of an associated method, whether taken by value or by reference.
             //Note that self receivers with a specified type, such as self: Box<Self>, are parsed as a FnArg::Typed.
-            //  XXX need to support case of self: i32 etc.
             syn::FnArg::Receiver(arg_self) => {
                 if arg_self.reference.is_some() {
                     let mutability = arg_self.mutability;
@@ -149,6 +574,8 @@ fn parse_args<'a, I: Iterator<Item=&'a syn::FnArg>>(decl: I) -> FnArgs {
                     args.args_with_types = quote! { &#lifetime #mutability self };
                     args.mutable_status = mutability.clone();
                     args.is_instance_method = true;
+                    args.borrows_self = true;
+                    args.self_lifetime = lifetime.cloned();
                 } else {
                     let mutability = arg_self.mutability;
                     args.args_with_types = quote!{ #mutability self };
@@ -157,6 +584,35 @@ fn parse_args<'a, I: Iterator<Item=&'a syn::FnArg>>(decl: I) -> FnArgs {
                     args.takes_self_ownership = true;
                 }
             },
+            syn::FnArg::Typed(captured) if is_self_pattern(&captured.pat) => {
+                let ref pat = captured.pat;
+                let ref ty = captured.ty;
+                args.args_with_types = quote! { #pat: #ty };
+                args.is_instance_method = true;
+                match **ty {
+                    syn::Type::Reference(ref reference) => {
+                        args.mutable_status = reference.mutability;
+                        args.borrows_self = true;
+                        args.self_lifetime = reference.lifetime.clone();
+                    },
+                    _ => {
+                        match single_type_arg(ty, "Pin") {
+                            Some(&syn::Type::Reference(ref reference)) => {
+                                args.typed_receiver = Some(TypedReceiver::Pin(reference.mutability));
+                            },
+                            Some(_) => {
+                                args.typed_receiver = Some(TypedReceiver::Shared);
+                            },
+                            None if single_type_arg(ty, "Box").is_some() => {
+                                args.typed_receiver = Some(TypedReceiver::Box);
+                            },
+                            None => {
+                                args.typed_receiver = Some(TypedReceiver::Shared);
+                            },
+                        }
+                    },
+                }
+            },
             syn::FnArg::Typed(captured) => {
                 let ty = &captured.ty;
                 let tok = concat!(arg_name, argc);
@@ -164,12 +620,13 @@ fn parse_args<'a, I: Iterator<Item=&'a syn::FnArg>>(decl: I) -> FnArgs {
                     args.args_with_types.extend(quote! {,});
                 }
 
-                if argc > 1 {
+                if !args.args_with_no_self_no_types.is_empty() {
                     args.args_with_no_self_no_types.extend(quote!{,});
                 }
 
                 args.args_with_types.extend(quote! { #tok: #ty });
                 args.args_with_no_self_no_types.extend(quote! { #tok });
+                args.typed_args.push((tok, (**ty).clone()));
             }
         }
 
@@ -179,9 +636,13 @@ fn parse_args<'a, I: Iterator<Item=&'a syn::FnArg>>(decl: I) -> FnArgs {
     args
 }
 
-fn make_return_tokens(no_return: bool, return_type: &proc_macro2::TokenStream) -> (proc_macro2::TokenStream, proc_macro2::TokenStream, proc_macro2::TokenStream) {
+fn make_return_tokens(no_return: bool, return_type: &proc_macro2::TokenStream, return_kind: &ReturnKind, future_kind: &FutureKind) -> (proc_macro2::TokenStream, proc_macro2::TokenStream, proc_macro2::TokenStream) {
     if no_return {
         (proc_macro2::TokenStream::new(), proc_macro2::TokenStream::new(), quote! { _ })
+    } else if future_kind.is_boxed() {
+        (quote! { -> #return_type }, quote! { ::std::boxed::Box::pin(async move { retval }) }, quote! { retval })
+    } else if *return_kind == ReturnKind::BorrowedFromSelf {
+        (quote! { -> #return_type }, quote! { &**method.retain(retval) }, quote! { retval })
     } else {
         (quote! { -> #return_type }, quote! { retval }, quote! { retval })
     }
@@ -198,609 +659,2990 @@ fn parse_return_type(output: &syn::ReturnType) -> (bool, proc_macro2::TokenStrea
     }
 }
 
-fn generate_static_name(base: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
-    concat!("Static_", base)
+// How a mocked fn hands its result back to the caller.
+enum FutureKind {
+    Ready,
+    // An `async fn`, which can await a configured future directly.
+    Async,
+    // A `Pin<Box<dyn Future>>` return, such as the methods generated by #[async_trait].
+    // Holds the bounds of the boxed future.
+    Boxed(proc_macro2::TokenStream),
+    // An `impl Future` return. Holds the bounds of the returned future.
+    Impl(proc_macro2::TokenStream),
 }
 
-fn generate_mock_method_name(trait_block: &syn::ItemTrait, prefix_opt: Option<proc_macro2::TokenStream>) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
-    let trait_name = quote_field!(&trait_block.ident);
-    let mx = concat!("Mock", trait_name); 
-    let mxf = concat!("MockMethodFor", trait_name);
-    (quote!{ #prefix_opt #mx}, quote!{ #prefix_opt #mxf })
+impl FutureKind {
+    fn is_boxed(&self) -> bool {
+        matches!(*self, FutureKind::Boxed(_) | FutureKind::Impl(_))
+    }
+
+    // Traits with `async fn` or `impl Trait` returns cannot be made into objects.
+    fn is_object_safe(&self) -> bool {
+        !matches!(*self, FutureKind::Async | FutureKind::Impl(_))
+    }
 }
 
+fn single_type_arg<'a>(ty: &'a syn::Type, wrapper: &str) -> Option<&'a syn::Type> {
+    if let syn::Type::Path(ref path) = *ty {
+        let last = path.path.segments.last().unwrap();
+        if last.ident == wrapper {
+            if let syn::PathArguments::AngleBracketed(ref args) = last.arguments {
+                if let Some(syn::GenericArgument::Type(ref inner)) = args.args.first() {
+                    return Some(inner);
+                }
+            }
+        }
+    }
 
+    None
+}
 
-fn generate_trait_fns(trait_block: &syn::ItemTrait, mut allow_object_fallback: bool, prefix: Option<proc_macro2::TokenStream>)
-                      -> TraitFn
-{
-    let trait_name = quote_field!(&trait_block.ident);
-    let generics = quote_field!(&trait_block.generics);
+// Finds the Output of a `Future` bound, along with the trait bounds to give the boxed future.
+fn parse_future_bounds(bounds: &syn::punctuated::Punctuated<syn::TypeParamBound, Token![+]>) -> Option<(syn::Type, proc_macro2::TokenStream)> {
+    let mut output = None;
+    let mut traits = Vec::new();
+    for bound in bounds.iter() {
+        if let syn::TypeParamBound::Trait(ref trait_bound) = *bound {
+            let last = trait_bound.path.segments.last().unwrap();
+            if last.ident == "Future" {
+                if let syn::PathArguments::AngleBracketed(ref args) = last.arguments {
+                    for arg in args.args.iter() {
+                        if let syn::GenericArgument::Binding(ref binding) = *arg {
+                            if binding.ident == "Output" {
+                                output = Some(binding.ty.clone());
+                            }
+                        }
+                    }
+                }
+            }
 
-    let mut mock_impl_methods = proc_macro2::TokenStream::new();
-    let mut fields = proc_macro2::TokenStream::new();
-    let mut ctor = proc_macro2::TokenStream::new();
-    let mut method_impls = proc_macro2::TokenStream::new();
-    let mut static_mocks_ctor = proc_macro2::TokenStream::new();
-    let mut static_mocks_def = proc_macro2::TokenStream::new();
-    let mut static_method_setup = proc_macro2::TokenStream::new();
-    let mut static_method_impl = proc_macro2::TokenStream::new();
-    let mut static_method_body = proc_macro2::TokenStream::new();
+            traits.push(trait_bound);
+        }
+    }
 
-    let (_, mock_method_name) = generate_mock_method_name(trait_block, prefix);
-    let static_name = generate_static_name(&trait_name);
-    // For each method in the Impl block, we create a "method_" name function that returns an
-    // object to mutate
-    for function in &trait_block.items {
-        match function {
-            syn::TraitItem::Method(fnx) => {
-                let name_stream = quote_field!(&fnx.sig.ident);
-                let method_ident = concat!("method_", name_stream);
-                let setter = concat!("set_", name_stream);
-                let unsafety = quote_field!(fnx.sig.unsafety);
+    output.map(|ty| (ty, quote! { #(#traits)+* }))
+}
 
-                let fn_args = parse_args(fnx.sig.inputs.iter());
-                let (no_return, return_type) = parse_return_type(&fnx.sig.output);
-                
-                let ref args_with_no_self_no_types = fn_args.args_with_no_self_no_types;
-                let ref args_with_types = fn_args.args_with_types;
+// Returns the kind of future a fn returns, and the type of the value it resolves to.
+fn parse_future_kind(sig: &syn::Signature) -> (FutureKind, syn::ReturnType) {
+    if sig.asyncness.is_some() {
+        return (FutureKind::Async, sig.output.clone());
+    }
 
-                if &format!("{}", return_type) == "Self" {
-                    panic!("Impls with the 'Self' return type are not supported. This is due to the fact that we generate an impl of your trait for a Mock struct. Methods that return Self will return an instance on our mock struct, not YOUR struct, which is not what you want.");
+    if let syn::ReturnType::Type(ref arrow, ref ty) = sig.output {
+        let found = match **ty {
+            syn::Type::ImplTrait(ref impl_trait) => {
+                parse_future_bounds(&impl_trait.bounds).map(|(output, bounds)| (FutureKind::Impl(bounds), output))
+            },
+            _ => {
+                match single_type_arg(ty, "Pin").and_then(|inner| single_type_arg(inner, "Box")) {
+                    Some(&syn::Type::TraitObject(ref object)) => {
+                        parse_future_bounds(&object.bounds).map(|(output, bounds)| (FutureKind::Boxed(bounds), output))
+                    },
+                    _ => None,
                 }
+            }
+        };
 
-                if !fn_args.is_instance_method {
-                    allow_object_fallback = false;
+        if let Some((kind, output)) = found {
+            return (kind, syn::ReturnType::Type(*arrow, Box::new(output)));
+        }
+    }
 
-                    let name = concat!(trait_name, "_Method_", name_stream);
-                    let clear_name = concat!("clear_", name_stream);
-                    let (return_statement,
-                         retval_statement,
-                         some_arg) = make_return_tokens(no_return, &return_type);
-                    let mock_method_body = generate_mock_method_body(&pub_token!(), &name);
-                    static_mocks_ctor.extend(quote!{ #method_ident: None, });
-                    static_mocks_def.extend(quote!{ #method_ident: Option<#name<#return_type>>, });
-                    static_method_body.extend(mock_method_body);
+    (FutureKind::Ready, sig.output.clone())
+}
 
-                    static_method_setup.extend(quote!{
-                        #[allow(dead_code)]
-                        pub fn #method_ident() -> #name<#return_type> {
-                            #name {
-                                call_num: ::std::sync::Mutex::new(1),
-                                current_num: ::std::sync::Mutex::new(1),
-                                retval: ::std::sync::Mutex::new(::std::collections::HashMap::new()),
-                                lambda: ::std::sync::Mutex::new(None),
-                                should_never_be_called: false,
-                                max_calls: None,
-                                min_calls: None,
-                            }
-                        }
-                        
-                        #[allow(dead_code)]
-                        pub fn #setter (x: #name<#return_type>) {
-                            let value = #static_name();
-                            let mut singleton = value.inner.lock().unwrap();
-                            singleton.#method_ident = Some(x);
-                        }
-                        
-                        #[allow(dead_code)]
-                        pub fn #clear_name () {
-                            let value = #static_name();
-                            let mut singleton = value.inner.lock().unwrap();
-                            singleton.#method_ident = None;
-                        }         
-                    });
+// A fn returning an iterator or a stream, which mock methods can set up with 'yields'.
+struct Sequence {
+    item: syn::Type,
+    // The bounds of the trait object that holds the items.
+    bounds: proc_macro2::TokenStream,
+    // The user's Stream trait, when the fn returns a stream.
+    stream_path: Option<syn::Path>,
+    pinned: bool,
+    is_impl: bool,
+}
 
-                    static_method_impl.extend(quote!{
-                         #unsafety fn #name_stream (#args_with_types) #return_statement {
-                            let value = #static_name();
-                            let singleton = value.inner.lock().unwrap();
-                            if let Some(ref method) = singleton.#method_ident {
-                                match method.call() {
-                                    Some(#some_arg) => {
-                                        #retval_statement
-                                    },
-                                    None => {
-                                        panic!("Called a static mock fnx without a value set.");
-                                    }
-                                }
-                            } else {
-                                panic!();
-                            }
-                        }
-                    });
+impl Sequence {
+    // `impl Trait` returns are stored as trait objects.
+    fn storage_type(&self) -> syn::Type {
+        let ref bounds = self.bounds;
+        if self.pinned {
+            parse_quote! { ::std::pin::Pin<::std::boxed::Box<dyn #bounds>> }
+        } else {
+            parse_quote! { ::std::boxed::Box<dyn #bounds> }
+        }
+    }
+}
 
-                    continue;
-                }
+// Finds the Item of an `Iterator` or `Stream` bound, along with the path of a Stream trait.
+fn parse_sequence_bounds(bounds: &syn::punctuated::Punctuated<syn::TypeParamBound, Token![+]>) -> Option<(syn::Type, Option<syn::Path>)> {
+    for bound in bounds.iter() {
+        if let syn::TypeParamBound::Trait(ref trait_bound) = *bound {
+            let last = trait_bound.path.segments.last().unwrap();
+            if last.ident != "Iterator" && last.ident != "Stream" {
+                continue;
+            }
 
-                // This is getting a litte confusing with all of the tokens here.
-                // This is defining the methods for #ident,
-                // which is generated per method of the impl trait.
-                // we generate a getter called method_foo, and a setter called set_foo.
-                // These methods will be put on the MockImpl struct.
-                mock_impl_methods.extend(quote! {
-                    pub fn #method_ident(&self) -> #mock_method_name <#return_type> {
-                        #mock_method_name {
-                            call_num: ::std::sync::Mutex::new(1),
-                            current_num: ::std::sync::Mutex::new(1),
-                            retval: ::std::sync::Mutex::new(::std::collections::HashMap::new()),
-                            lambda: ::std::sync::Mutex::new(None),
-                            should_never_be_called: false,
-                            max_calls: None,
-                            min_calls: None,
+            if let syn::PathArguments::AngleBracketed(ref args) = last.arguments {
+                for arg in args.args.iter() {
+                    if let syn::GenericArgument::Binding(ref binding) = *arg {
+                        if binding.ident == "Item" {
+                            let mut path = None;
+                            if last.ident == "Stream" {
+                                let mut stream_path = trait_bound.path.clone();
+                                stream_path.segments.last_mut().unwrap().arguments = syn::PathArguments::None;
+                                path = Some(stream_path);
+                            }
+
+                            return Some((binding.ty.clone(), path));
                         }
                     }
+                }
+            }
+        }
+    }
 
-                    pub fn #setter(&mut self, method: #mock_method_name <#return_type>) {
-                        self.#name_stream = Some(method);
-                    }
-                });
+    None
+}
 
-                // The fields on the MockImpl struct.
-                fields.extend(quote! { #name_stream
-                                        : Option <#mock_method_name <#return_type>> , });
+fn parse_sequence(ty: &syn::Type) -> Option<Sequence> {
+    if let syn::Type::ImplTrait(ref impl_trait) = *ty {
+        let ref bounds = impl_trait.bounds;
+        return parse_sequence_bounds(bounds).map(|(item, stream_path)| Sequence {
+            item: item,
+            bounds: quote! { #bounds },
+            pinned: stream_path.is_some(),
+            stream_path: stream_path,
+            is_impl: true,
+        });
+    }
 
-                // The values that we will set in the ctor for the above defined
-                // 'fields' of MockImpl
-                ctor.extend(quote! { #name_stream : None, });
+    let (inner, pinned) = match single_type_arg(ty, "Pin") {
+        Some(inner) => (inner, true),
+        None => (ty, false),
+    };
 
-                let mut_token = quote_field!(fn_args.mutable_status);
-                let get_ref;
-                if fn_args.mutable_status.is_some() {
-                    get_ref = quote! { .as_mut() }
-                } else {
-                    get_ref = quote! { .as_ref() }
-                }
+    match single_type_arg(inner, "Box") {
+        Some(&syn::Type::TraitObject(ref object)) => {
+            let ref bounds = object.bounds;
+            parse_sequence_bounds(bounds).map(|(item, stream_path)| Sequence {
+                item: item,
+                bounds: quote! { #bounds },
+                stream_path: stream_path,
+                pinned: pinned,
+                is_impl: false,
+            })
+        },
+        _ => None,
+    }
+}
 
-                let fallback;
-                if fn_args.takes_self_ownership {
-                    fallback = quote! {
-                        panic!("Using a fallback for methods that take ownership of self is not supported. This is because the internals of our library do not know the size of your implementation at compile time, and will not be able to call the fallback method");
-                    };
-                } else if allow_object_fallback {
-                    fallback = quote! {
-                        let ref #mut_token fallback = self.fallback
-                            #get_ref
-                        .expect("Called method without either a fallback, or a set result");
-                        fallback.#name_stream(#args_with_no_self_no_types)
-                    };
-                } else {
-                    fallback = quote! {
-                        panic!("Using a fallback has been disabled for this use case. We cannot use a fallback for Sized Types.");
-                    };
-                }
-
-                let (return_statement,
-                     retval_statement,
-                     some_arg) = make_return_tokens(no_return, &return_type);
+fn mentions_ident(tokens: proc_macro2::TokenStream, ident: &syn::Ident) -> bool {
+    tokens.into_iter().any(|tree| match tree {
+        proc_macro2::TokenTree::Ident(ref other) => other == ident,
+        proc_macro2::TokenTree::Group(ref group) => mentions_ident(group.stream(), ident),
+        _ => false,
+    })
+}
 
-                method_impls.extend(quote! {
-                    #unsafety fn #name_stream(#args_with_types) #return_statement {
-                        match self.#name_stream.as_ref() {
-                            Some(method) => {
-                                match method.call() {
-                                    Some(#some_arg) => {
-                                        // The mock has completed its duty.
-                                        #retval_statement
-                                    },
-                                    
-                                    None => {
-                                        #fallback
-                                    }
-                                }
-                            },
-                            
-                            None => {
-                                // Check if there is a fallback
-                                #fallback
-                            }
-                        }
-                    }
-                });
+// Implements the Sequence trait of 'mock_method_name' for the type storing a sequence,
+// along with the user's Stream trait for the items of a stream.
+fn generate_sequence_impls(mock_method_name: &proc_macro2::TokenStream,
+                           sequence: &Sequence,
+                           result_type: &proc_macro2::TokenStream,
+                           generics: &syn::Generics) -> Vec<proc_macro2::TokenStream> {
+    let sequence_name = concat!(mock_method_name, "Sequence");
+    let yield_name = concat!(mock_method_name, "Yield");
+    let ref item = sequence.item;
+    let ref bounds = sequence.bounds;
+    let params: Vec<_> = generics.type_params()
+        .map(|param| &param.ident)
+        .filter(|ident| mentions_ident(quote! { #item }, ident))
+        .collect();
+    let constructor = if sequence.pinned {
+        quote! { ::std::boxed::Box::pin }
+    } else {
+        quote! { ::std::boxed::Box::new }
+    };
 
+    let mut impls = vec![quote! {
+        impl<#(#params),*> #sequence_name for #result_type
+            where #yield_name<#item>: #bounds + 'static {
+            type Item = #item;
 
-            },
-            _ => { panic!("Mocking a struct with feature not supported."); }
+            fn from_steps(steps: Vec<Option<#item>>) -> Self {
+                #constructor(#yield_name::new(steps))
+            }
         }
+    }];
 
-
-    }
-
-    if allow_object_fallback {
-        fields.extend(quote!{ fallback: Option<Box<dyn #trait_name #generics>>, });
-        ctor.extend(quote!{ fallback: None, });
-        mock_impl_methods.extend(quote!{
+    if let Some(ref stream_path) = sequence.stream_path {
+        impls.push(quote! {
             #[allow(non_camel_case_types)]
-            pub fn set_fallback<__TYPE_NAME: 'static + #trait_name #generics>(&mut self, t: __TYPE_NAME) {
-                self.fallback = Some(Box::new(t));
+            impl<__ITEM_NAME> #stream_path for #yield_name<__ITEM_NAME> {
+                type Item = __ITEM_NAME;
+
+                fn poll_next(self: ::std::pin::Pin<&mut Self>, cx: &mut ::std::task::Context<'_>) -> ::std::task::Poll<Option<__ITEM_NAME>> {
+                    match self.get_mut().steps.pop_front() {
+                        Some(Some(item)) => ::std::task::Poll::Ready(Some(item)),
+                        Some(None) => {
+                            cx.waker().wake_by_ref();
+                            ::std::task::Poll::Pending
+                        },
+                        None => ::std::task::Poll::Ready(None),
+                    }
+                }
             }
         });
     }
-    
-    TraitFn { mock_impl_methods: mock_impl_methods,
-     fields: fields,
-     ctor: ctor,
-     method_impls: method_impls,
-     static_method_setup: static_method_setup,
-     static_method_impl: static_method_impl,
-     static_method_body: static_method_body,
-     static_mocks_ctor: static_mocks_ctor,
-     static_mocks_def: static_mocks_def }
+
+    impls
 }
 
-fn parse_trait(trait_block: syn::ItemTrait, raw_trait: &syn::Item) -> proc_macro2::TokenStream {
-    let trait_name = quote_field!(&trait_block.ident);
-    let generics = quote_field!(&trait_block.generics);
-    let where_clause = quote_field!(&trait_block.generics.where_clause);
-    let unsafety = quote_field!(&trait_block.unsafety);
-    
-    let pubtok = quote_field!(&trait_block.vis);
-    let mut derived_additions = proc_macro2::TokenStream::new();
-    
-    let (impl_name,
-         mock_method_name) = generate_mock_method_name(&trait_block, None);
-    
+// The type stored in the mock method for a fn, which differs from the return type when
+// the return type borrows. References borrowed from self are stored as boxes, and any
+// other elided or method level lifetime is replaced with 'static.
+fn parse_result_type(output: &syn::ReturnType, generics: &syn::Generics, fn_args: &FnArgs, future_kind: &FutureKind) -> (proc_macro2::TokenStream, ReturnKind) {
+    let ty = match *output {
+        syn::ReturnType::Default => return (quote! { () }, ReturnKind::Owned),
+        syn::ReturnType::Type(_, ref ty) => ty,
+    };
 
-    let mut impls_sized = false;
-    for item in trait_block.supertraits.iter() {
-        if let &syn::TypeParamBound::Trait(ref bound) = item {
-            let ref trait_ref = bound.path;
-            let ref ident = trait_ref.segments.last().unwrap().ident;
-            if ident == "Sized" {
-                impls_sized = true;
-            }
-        }
-    }
+    let mut eraser = LifetimeEraser {
+        method_lifetimes: generics.lifetimes().map(|def| &def.lifetime.ident).collect(),
+    };
 
-    let trait_fns = generate_trait_fns(&trait_block, !impls_sized, None);
-    let mut mock_impl_methods = trait_fns.mock_impl_methods;
-    let mut fields = trait_fns.fields;
-    let mut ctor = trait_fns.ctor;
-    let method_impls = trait_fns.method_impls;
-    let static_method_setup = trait_fns.static_method_setup;
-    let static_method_impl = trait_fns.static_method_impl;
-    let static_method_body = trait_fns.static_method_body;
-    let static_mocks_ctor = trait_fns.static_mocks_ctor;
-    let static_mocks_def = trait_fns.static_mocks_def;
+    if let syn::Type::Reference(ref reference) = **ty {
+        let from_self = match reference.lifetime {
+            None => true,
+            Some(ref lifetime) => lifetime.ident == "_" || Some(lifetime) == fn_args.self_lifetime.as_ref(),
+        };
 
-    {
-        let mut bounds = BOUNDS_MAP.lock().unwrap();
-        for item in trait_block.supertraits.iter() {
-            if let &syn::TypeParamBound::Trait(ref trait_bound) = item {
-                let ref trait_ref = trait_bound.path;
-                let ref ident = trait_ref.segments.last().unwrap().ident;
-                let qt = quote!{#ident};
-                let path_str = format!("{}", qt);
-                if let Some(impl_body_str) = bounds.get_mut(&path_str) {
-                    let impl_body = syn::parse_str::<syn::ItemTrait>(impl_body_str).unwrap();
-                    let segments: syn::punctuated::Punctuated<_,_> = trait_ref.segments.iter().cloned()
-                        .take(trait_ref.segments.len() - 1).collect();
-                    let mut basis = None;
-                    if segments.len() > 0 {
-                        let path = syn::Path {
-                            leading_colon: trait_ref.leading_colon,
-                            segments: segments,
-                        };
-                        basis = Some(quote!{ #path :: });
-                    }
-                    
-                    let ref base_generics = impl_body.generics;
-                    let ret = generate_trait_fns(&impl_body, false, basis);
-                    let base_mock_impl_methods = ret.mock_impl_methods;
-                    let base_fields = ret.fields;
-                    let base_ctor = ret.ctor;
-                    let base_method_impls = ret.method_impls;
-
-                    mock_impl_methods.extend(quote! { #base_mock_impl_methods });
-                    fields.extend(quote! { #base_fields });
-                    ctor.extend(quote! { #base_ctor });
-                    derived_additions.extend(quote! {
-                        impl #base_generics #trait_ref #base_generics
-                            for #impl_name #generics #where_clause {
-                            #base_method_impls
-                        }
-                    });
-                 }
+        if let FutureKind::Ready = *future_kind {
+            if from_self && fn_args.borrows_self && reference.mutability.is_none() {
+                let mut elem = (*reference.elem).clone();
+                syn::visit_mut::VisitMut::visit_type_mut(&mut eraser, &mut elem);
+                return (quote! { Box<#elem> }, ReturnKind::BorrowedFromSelf);
             }
         }
     }
 
-    fields.extend(quote! { print_string: ::std::sync::Mutex<Option<String>>, });
-    ctor.extend(quote! { print_string : ::std::sync::Mutex::new(None), });
-    
-    let mock_method_body = generate_mock_method_body(&pubtok, &mock_method_name);
-    let static_struct_name = concat!("STATIC__", trait_name);
-    let mut static_content = quote!{ };
-    if format!("{}", static_mocks_def).len() > 0 {
-        let static_name = generate_static_name(&trait_name);
-        let mut_static = make_mut_static(&static_name, &static_struct_name, &quote!{
-            #static_struct_name { #static_mocks_ctor }
-        });
+    let mut ty = match parse_sequence(ty) {
+        Some(ref sequence) if sequence.is_impl => sequence.storage_type(),
+        _ => (**ty).clone(),
+    };
+    syn::visit_mut::VisitMut::visit_type_mut(&mut eraser, &mut ty);
+    (quote! { #ty }, ReturnKind::Owned)
+}
 
-        static_content = quote! {
-            #[allow(non_camel_case_types)]
-            struct #static_struct_name {
-                #static_mocks_def
-            }
-            
-            #mut_static
+// Generates the value returned for a mock method configured with 'returns_arg', as a match
+// on 'index'. Only arguments whose declared type is the return type, or that can be wrapped
+// in the returned Option, are candidates. A return borrowed from self can't outlive the
+// arguments, so it has none.
+fn generate_arg_returns(fn_args: &FnArgs, output: &syn::ReturnType, return_kind: &ReturnKind, future_kind: &FutureKind) -> proc_macro2::TokenStream {
+    let mut branches = proc_macro2::TokenStream::new();
+    if let (&syn::ReturnType::Type(_, ref ret), &ReturnKind::Owned) = (output, return_kind) {
+        let ret_str = format!("{}", quote!{ #ret });
+        let option_str = single_type_arg(ret, "Option").map(|inner| format!("{}", quote!{ #inner }));
 
-            #static_method_body
-        };
-    }
+        for (index, (tok, ty)) in fn_args.typed_args.iter().enumerate() {
+            let ty_str = format!("{}", quote!{ #ty });
+            let mut value = if ty_str == ret_str {
+                quote! { #tok }
+            } else if Some(&ty_str) == option_str.as_ref() {
+                quote! { Some(#tok) }
+            } else {
+                continue;
+            };
 
-    let stream = quote! {
-        #raw_trait
+            if future_kind.is_boxed() {
+                value = quote! { ::std::boxed::Box::pin(async move { #value }) };
+            }
 
-        #static_content
+            branches.extend(quote! { #index => #value, });
+        }
+    }
 
-        #[allow(dead_code)]
-        #pubtok struct #impl_name #generics #where_clause {
-            #fields
+    quote! {
+        match index {
+            #branches
+            _ => panic!("returns_arg({}) does not name an argument that can be returned from this method", index),
         }
+    }
+}
 
-        #[allow(dead_code)]
-        #[allow(non_camel_case_types)]
-        impl #generics ::std::fmt::Debug for #impl_name #generics #where_clause {
-            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
-                // panic!("Printing mocks is not supported. Do not call println on mocks.");
-                let print_string = self.print_string.lock().unwrap();
-                let mut message = "This is the default message for a mock object. You can set the print behavior via a call to 'set_print_string'";
-                if print_string.as_ref().is_some() {
-                    message = print_string.as_ref().unwrap();
+// Generates the body of a mocked fn. After running 'setup', 'method' evaluates to the
+// Option<&MockMethod> for the fn. 'no_result' runs when the mock method has nothing to
+// return for this call, and 'unset' when no mock method has been set.
+fn generate_fn_body(setup: &proc_macro2::TokenStream,
+                    method: &proc_macro2::TokenStream,
+                    future_kind: &FutureKind,
+                    (some_arg, retval_statement): (&proc_macro2::TokenStream, &proc_macro2::TokenStream),
+                    arg_returns: &proc_macro2::TokenStream,
+                    no_result: &proc_macro2::TokenStream,
+                    unset: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let dispatch = quote! {
+        match #method {
+            Some(method) => {
+                match method.call() {
+                    Some(#some_arg) => {
+                        // The mock has completed its duty.
+                        #retval_statement
+                    },
+
+                    None => {
+                        match method.returned_arg {
+                            Some(index) => #arg_returns,
+                            None => {
+                                #no_result
+                            }
+                        }
+                    }
                 }
+            },
 
-                f.pad(&message)
+            None => {
+                #unset
             }
         }
+    };
 
-        // Your mocks may not use all of these functions, so it's fine to allow
-        // dead code in this impl block.
-        #[allow(dead_code)]
-        impl #generics #impl_name #generics #where_clause {
-            #mock_impl_methods
-            #static_method_setup
+    // Configured futures are taken before anything else, so no lock is held while they run.
+    let configured_future = quote! {
+        let configured_future = {
+            #setup
+            let future = #method.and_then(|method| method.call_future());
+            future
+        };
+    };
 
-            pub fn new() -> #impl_name #generics {
-                #impl_name { #ctor }
+    match *future_kind {
+        FutureKind::Ready => {
+            quote! {
+                #setup
+                #dispatch
             }
+        },
+        FutureKind::Async => {
+            quote! {
+                #configured_future
+                if let Some(future) = configured_future {
+                    return future.await;
+                }
 
-            pub fn set_print_string(&self, arg_string: String) {
-                let mut print_string = self.print_string.lock().unwrap();
-                *print_string = Some(arg_string);
+                #setup
+                #dispatch
+            }
+        },
+        FutureKind::Boxed(ref bounds) | FutureKind::Impl(ref bounds) => {
+            quote! {
+                #configured_future
+                #setup
+                let future: ::std::pin::Pin<Box<dyn #bounds + '_>> = match configured_future {
+                    Some(future) => ::std::boxed::Box::pin(async move { future.await }),
+                    None => #dispatch,
+                };
+                future
             }
         }
+    }
+}
 
-        #mock_method_body
+fn generate_static_name(base: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    concat!("Static_", base)
+}
 
-        #unsafety impl #generics #trait_name #generics for #impl_name #generics #where_clause {
-            #method_impls
-            #static_method_impl
-        }
+// The names, visibility and storage of a mock, and the trait that it implements.
+struct MockTarget {
+    vis: proc_macro2::TokenStream,
+    impl_name: proc_macro2::TokenStream,
+    mock_method_name: proc_macro2::TokenStream,
+    // None for the mocks of inherent impls, whose methods are implemented on the mock itself.
+    trait_ref: Option<proc_macro2::TokenStream>,
+    // The type the mock of an inherent impl dereferences to, which holds the functions that
+    // set it up, so that they can't collide with the methods of the impl.
+    control_name: Option<proc_macro2::TokenStream>,
+    // Static methods that return Self, which create a new mock.
+    constructors: Vec<syn::Signature>,
+    // The prefix of the names generated for static methods.
+    static_base: proc_macro2::TokenStream,
+    // Where the expectations of static methods are kept.
+    storage: Storage,
+}
 
+impl MockTarget {
+    fn for_trait(trait_block: &syn::ItemTrait, args: &MockArgs) -> MockTarget {
+        let trait_name = quote_field!(&trait_block.ident);
+        let (_, ty_generics, _) = trait_block.generics.split_for_impl();
+        let base = match args.name {
+            Some(ref name) => quote! { #name },
+            None => trait_name.clone(),
+        };
+        let vis = match args.vis {
+            Some(ref vis) => quote! { #vis },
+            None => quote_field!(&trait_block.vis),
+        };
 
-        #derived_additions
-    };
+        let impl_name = match args.name {
+            Some(_) => base.clone(),
+            None => concat!("Mock", trait_name),
+        };
 
-    {
-        let mut map = BOUNDS_MAP.lock().unwrap();
-        let name = format!("{}", trait_name);
-        let serial = format!("{}", quote!{ #trait_block });
-        map.insert(name, serial);
+        MockTarget {
+            vis: vis,
+            impl_name: impl_name,
+            mock_method_name: concat!("MockMethodFor", base),
+            trait_ref: Some(quote! { #trait_name #ty_generics }),
+            control_name: None,
+            constructors: Vec::new(),
+            static_base: base,
+            storage: args.storage(),
+        }
     }
-
-    stream
 }
 
-fn parse_foreign_functions(func_block: syn::ItemForeignMod, _raw_block: &syn::Item) -> proc_macro2::TokenStream {
-    let mut result = proc_macro2::TokenStream::new();
-    let mut extern_mocks_ctor_args = proc_macro2::TokenStream::new();
-    let mut extern_mocks_def = proc_macro2::TokenStream::new();
+// The methods of supertraits are mocked with the mock method struct of the derived trait,
+// so 'target' names the mock, even when 'trait_block' is a supertrait.
+fn generate_trait_fns(trait_block: &syn::ItemTrait, mut allow_object_fallback: bool, target: &MockTarget)
+                      -> TraitFn
+{
+    let ref trait_name = target.static_base;
+    let ref mock_method_name = target.mock_method_name;
+    let method_vis = match target.trait_ref {
+        Some(_) => proc_macro2::TokenStream::new(),
+        None => pub_token!(),
+    };
 
-    let abi;
-    let type_name;
-    if let Some(ref name) = func_block.abi.name {
-        abi = quote!{ extern #name };
-        type_name = name.value().replace("extern", "").replace("\"", "");
-    } else {
-        abi = quote!{ extern };
-        type_name = String::from("Rust");
+    let mut mock_impl_methods = proc_macro2::TokenStream::new();
+    let mut fields = proc_macro2::TokenStream::new();
+    let mut ctor = proc_macro2::TokenStream::new();
+    let mut method_impls = proc_macro2::TokenStream::new();
+    let mut static_mocks_ctor = proc_macro2::TokenStream::new();
+    let mut static_mocks_def = proc_macro2::TokenStream::new();
+    let mut static_method_setup = proc_macro2::TokenStream::new();
+    let mut static_method_impl = proc_macro2::TokenStream::new();
+    let mut static_method_body = proc_macro2::TokenStream::new();
+    let mut sequence_impls = Vec::new();
+
+    // A fallback is stored as a trait object, so it can only be used if every method
+    // can be called through one.
+    for function in &trait_block.items {
+        if let syn::TraitItem::Method(ref fnx) = *function {
+            let is_instance_method = parse_args(fnx.sig.inputs.iter()).is_instance_method;
+            let is_generic = fnx.sig.generics.type_params().count() > 0;
+            let returns_impl = match fnx.sig.output {
+                syn::ReturnType::Type(_, ref ty) => matches!(**ty, syn::Type::ImplTrait(_)),
+                syn::ReturnType::Default => false,
+            };
+            if !is_instance_method || is_generic || returns_impl || !parse_future_kind(&fnx.sig).0.is_object_safe() {
+                allow_object_fallback = false;
+            }
+        }
     }
-    
-    let extern_name = concat!("Extern", type_name, "Mocks");//syn::Ident::new(&format!("Extern{}Mocks", type_name), proc_macro2::Span::call_site());
-    let static_name = concat!("Static", extern_name);
-    for item in func_block.items {
-        match item {
-            syn::ForeignItem::Fn(ref fn_item) => {
-                let ref decl = fn_item.sig;
-                let ref generics = decl.generics;
 
-                if decl.variadic.is_some() {
-                    panic!("Mocking variadic functions not yet supported. This will be added in the future.");
-                }
+    let static_name = generate_static_name(trait_name);
+    // For each method in the Impl block, we create a "method_" name function that returns an
+    // object to mutate
+    for function in &trait_block.items {
+        match function {
+            syn::TraitItem::Method(fnx) => {
+                let name_stream = quote_field!(&fnx.sig.ident);
+                let method_ident = concat!("method_", name_stream);
+                let setter = concat!("set_", name_stream);
+                let unsafety = quote_field!(fnx.sig.unsafety);
+                let asyncness = quote_field!(fnx.sig.asyncness);
 
-                if generics.type_params().count() > 0 || generics.lifetimes().count() > 0 {
-                    panic!("Mocking extern functions with generics/lifetimes not yet supported.");
+                let fn_args = parse_args(fnx.sig.inputs.iter());
+                let (no_return, return_type) = parse_return_type(&fnx.sig.output);
+                let (future_kind, value_output) = parse_future_kind(&fnx.sig);
+                let (result_type, return_kind) = parse_result_type(&value_output, &fnx.sig.generics, &fn_args, &future_kind);
+                let arg_returns = generate_arg_returns(&fn_args, &value_output, &return_kind, &future_kind);
+                let ref method_generics = fnx.sig.generics;
+                let ref method_where_clause = fnx.sig.generics.where_clause;
+                
+                let ref args_with_no_self_no_types = fn_args.args_with_no_self_no_types;
+                let ref args_with_types = fn_args.args_with_types;
+
+                if &format!("{}", return_type) == "Self" {
+                    panic!("Impls with the 'Self' return type are not supported. This is due to the fact that we generate an impl of your trait for a Mock struct. Methods that return Self will return an instance on our mock struct, not YOUR struct, which is not what you want.");
                 }
 
-                let fn_args = parse_args(decl.inputs.iter());
-                let ref args_with_types = fn_args.args_with_types;
-                let (no_return, return_type) = parse_return_type(&decl.output);
-                
-                let base_name = quote_field!(&decl.ident);
-                let name = concat!("Method_", base_name);
-                let name_lc = concat!("method_", base_name);
-                let setter_name = concat!("set_", base_name);
-                let clear_name = concat!("clear_", base_name);
-                extern_mocks_ctor_args = quote!{ #extern_mocks_ctor_args #name_lc: None, };
-                extern_mocks_def = quote!{ #extern_mocks_def #name_lc: Option<#name<#return_type>>, };
-                let pubtok = quote_field!(&fn_item.vis);                
-                let (return_statement,
-                     retval_statement,
-                     some_arg) = make_return_tokens(no_return, &return_type);
-                // Hardcode pub to true here, so
-                // that other modules can universally use Extern<>Mocks
-                let mock_method_body = generate_mock_method_body(&pub_token!(),
-                                                                 &quote!{ #name });
-                result = quote! {
-                    #result
-                    #mock_method_body
+                let sequence = syn::parse2::<syn::Type>(result_type.clone()).ok().and_then(|ty| parse_sequence(&ty));
 
-                    impl #extern_name {
+                if !fn_args.is_instance_method {
+                    let name = concat!(trait_name, "_Method_", name_stream);
+                    if let Some(ref sequence) = sequence {
+                        sequence_impls.extend(generate_sequence_impls(&name, sequence, &result_type, &trait_block.generics));
+                    }
+
+                    let clear_name = concat!("clear_", name_stream);
+                    let scoped_setter = generate_scoped_setter(&concat!("scoped_", name_stream),
+                                                               &concat!(target.impl_name, "Guard"),
+                                                               &static_name,
+                                                               &method_ident,
+                                                               &quote! { #name<#result_type> },
+                                                               &quote! {});
+                    let (return_statement,
+                         retval_statement,
+                         some_arg) = make_return_tokens(no_return, &return_type, &ReturnKind::Owned, &future_kind);
+                    let mock_method_body = generate_mock_method_body(&pub_token!(), &name, &target.storage.send_bound());
+                    let mock_method_ctor = generate_mock_method_ctor(&name, &future_kind);
+                    static_mocks_ctor.extend(quote!{ #method_ident: None, });
+                    static_mocks_def.extend(quote!{ #method_ident: Option<#name<#result_type>>, });
+                    static_method_body.extend(mock_method_body);
+
+                    static_method_setup.extend(quote!{
                         #[allow(dead_code)]
-                        pub fn #name_lc() -> #name<#return_type> {
-                            #name {
-                                call_num: ::std::sync::Mutex::new(1),
-                                current_num: ::std::sync::Mutex::new(1),
-                                retval: ::std::sync::Mutex::new(::std::collections::HashMap::new()),
-                                lambda: ::std::sync::Mutex::new(None),
-                                should_never_be_called: false,
-                                max_calls: None,
-                                min_calls: None,
-                            }
+                        pub fn #method_ident() -> #name<#result_type> {
+                            #mock_method_ctor
                         }
-
+                        
                         #[allow(dead_code)]
-                        pub fn #setter_name (x: #name<#return_type>) {
+                        pub fn #setter (x: #name<#result_type>) {
                             let value = #static_name();
-                            let mut singleton = value.inner.lock().unwrap();
-                            singleton.#name_lc = Some(x);
+                            let mut singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                            singleton.#method_ident = Some(x);
                         }
-
+                        
                         #[allow(dead_code)]
                         pub fn #clear_name () {
                             let value = #static_name();
-                            let mut singleton = value.inner.lock().unwrap();
-                            singleton.#name_lc = None;
+                            let mut singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                            singleton.#method_ident = None;
                         }
-                        
-                    }
+
+                        #scoped_setter
+                    });
+
+                    let fn_body = generate_fn_body(&quote! {
+                                                       let value = #static_name();
+                                                       let singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                                                   },
+                                                   &quote! { singleton.#method_ident.as_ref() },
+                                                   &future_kind,
+                                                   (&some_arg, &retval_statement),
+                                                   &arg_returns,
+                                                   &quote! { panic!("Called a static mock fnx without a value set."); },
+                                                   &quote! { panic!(); });
+
+                    static_method_impl.extend(quote!{
+                         #method_vis #unsafety #asyncness fn #name_stream #method_generics (#args_with_types) #return_statement #method_where_clause {
+                            #fn_body
+                        }
+                    });
+
+                    continue;
+                }
+
+                // This is getting a litte confusing with all of the tokens here.
+                // This is defining the methods for #ident,
+                // which is generated per method of the impl trait.
+                // we generate a getter called method_foo, and a setter called set_foo.
+                // These methods will be put on the MockImpl struct.
+                if let Some(ref sequence) = sequence {
+                    sequence_impls.extend(generate_sequence_impls(mock_method_name, sequence, &result_type, &trait_block.generics));
+                }
+
+                let mock_method_ctor = generate_mock_method_ctor(mock_method_name, &future_kind);
+                mock_impl_methods.extend(quote! {
+                    pub fn #method_ident(&self) -> #mock_method_name <#result_type> {
+                        #mock_method_ctor
+                    }
+
+                    pub fn #setter(&mut self, method: #mock_method_name <#result_type>) {
+                        self.#name_stream = Some(method);
+                    }
+                });
+
+                // The fields on the MockImpl struct.
+                fields.extend(quote! { #name_stream
+                                        : Option <#mock_method_name <#result_type>> , });
+
+                // The values that we will set in the ctor for the above defined
+                // 'fields' of MockImpl
+                ctor.extend(quote! { #name_stream : None, });
+
+                let get_ref;
+                if fn_args.mutable_status.is_some() {
+                    get_ref = quote! { .as_mut() }
+                } else {
+                    get_ref = quote! { .as_ref() }
+                }
+
+                // A builder method of an inherent impl passes the mock on when it has no result.
+                let (_, ty_generics, _) = trait_block.generics.split_for_impl();
+                let ref impl_name = target.impl_name;
+                let returns_mock = target.control_name.is_some()
+                    && format!("{}", return_type) == format!("{}", quote! { #impl_name #ty_generics });
+
+                let fallback;
+                if fn_args.takes_self_ownership && returns_mock {
+                    fallback = quote! { self };
+                } else if fn_args.takes_self_ownership {
+                    fallback = quote! {
+                        panic!("Using a fallback for methods that take ownership of self is not supported. This is because the internals of our library do not know the size of your implementation at compile time, and will not be able to call the fallback method");
+                    };
+                } else if let Some(TypedReceiver::Shared) = fn_args.typed_receiver {
+                    fallback = quote! {
+                        panic!("Using a fallback for methods that take self as an Rc, Arc or other shared pointer is not supported. The fallback is owned by the mock, and cannot be shared through the same pointer.");
+                    };
+                } else if fn_args.typed_receiver.is_some() && !allow_object_fallback {
+                    fallback = quote! {
+                        panic!("Using a fallback has been disabled for this use case. We cannot use a fallback for Sized Types.");
+                    };
+                } else if let Some(TypedReceiver::Box) = fn_args.typed_receiver {
+                    fallback = quote! {
+                        let fallback = self.fallback
+                            .expect("Called method without either a fallback, or a set result");
+                        fallback.#name_stream(#args_with_no_self_no_types)
+                    };
+                } else if let Some(TypedReceiver::Pin(Some(_))) = fn_args.typed_receiver {
+                    // The fallback is boxed, so it stays in place for as long as the mock does.
+                    fallback = quote! {
+                        let this = unsafe { self.get_unchecked_mut() };
+                        let fallback = this.fallback
+                            .as_mut()
+                            .expect("Called method without either a fallback, or a set result");
+                        unsafe { ::std::pin::Pin::new_unchecked(&mut **fallback) }.#name_stream(#args_with_no_self_no_types)
+                    };
+                } else if let Some(TypedReceiver::Pin(None)) = fn_args.typed_receiver {
+                    fallback = quote! {
+                        let fallback = self.get_ref().fallback
+                            .as_ref()
+                            .expect("Called method without either a fallback, or a set result");
+                        unsafe { ::std::pin::Pin::new_unchecked(&**fallback) }.#name_stream(#args_with_no_self_no_types)
+                    };
+                } else if allow_object_fallback {
+                    // The fallback is bound by value, so that a returned future may borrow it for as long as self.
+                    fallback = quote! {
+                        let fallback = self.fallback
+                            #get_ref
+                        .expect("Called method without either a fallback, or a set result");
+                        fallback.#name_stream(#args_with_no_self_no_types)
+                    };
+                } else {
+                    fallback = quote! {
+                        panic!("Using a fallback has been disabled for this use case. We cannot use a fallback for Sized Types.");
+                    };
+                }
+
+                let (return_statement,
+                     retval_statement,
+                     some_arg) = make_return_tokens(no_return, &return_type, &return_kind, &future_kind);
+
+                // Check if there is a fallback when the mock method has no result
+                let fn_body = generate_fn_body(&quote! { },
+                                               &quote! { self.#name_stream.as_ref() },
+                                               &future_kind,
+                                               (&some_arg, &retval_statement),
+                                               &arg_returns,
+                                               &fallback,
+                                               &fallback);
+
+                method_impls.extend(quote! {
+                    #method_vis #unsafety #asyncness fn #name_stream #method_generics (#args_with_types) #return_statement #method_where_clause {
+                        #fn_body
+                    }
+                });
+
+
+            },
+            _ => { panic!("Mocking a struct with feature not supported."); }
+        }
+
+
+    }
+
+    if let (true, &Some(ref trait_ref)) = (allow_object_fallback, &target.trait_ref) {
+        fields.extend(quote!{ fallback: Option<Box<dyn #trait_ref>>, });
+        ctor.extend(quote!{ fallback: None, });
+        mock_impl_methods.extend(quote!{
+            #[allow(non_camel_case_types)]
+            pub fn set_fallback<__TYPE_NAME: 'static + #trait_ref>(&mut self, t: __TYPE_NAME) {
+                self.fallback = Some(Box::new(t));
+            }
+        });
+    }
+    
+    TraitFn { mock_impl_methods: mock_impl_methods,
+     fields: fields,
+     ctor: ctor,
+     method_impls: method_impls,
+     static_method_setup: static_method_setup,
+     static_method_impl: static_method_impl,
+     static_method_body: static_method_body,
+     static_mocks_ctor: static_mocks_ctor,
+     static_mocks_def: static_mocks_def,
+     sequence_impls: sequence_impls }
+}
+
+// Supertraits that are implemented by every mock, or that have no methods to mock.
+// Debug prints the string set with 'set_print_string', and Display is mocked by 'parse_trait'.
+const IMPLEMENTED_SUPERTRAITS: &[&str] = &["Sized", "Send", "Sync", "Unpin", "Copy", "Any", "Debug", "Display"];
+
+// The number of description macros emitted so far, see 'generate_description'.
+static DESCRIPTIONS: AtomicUsize = AtomicUsize::new(0);
+
+// The crates a standard library trait is named through, as in `std::io::Write`.
+const STD_CRATES: &[&str] = &["std", "core", "alloc"];
+
+// The standard library traits in the prelude, which name std when used without a path.
+// Hash is not in the prelude, but its derive macro is.
+const PRELUDE_TRAITS: &[&str] = &[
+    "Clone", "PartialEq", "Iterator", "Eq", "PartialOrd", "Ord", "Default", "Hash", "Drop",
+    "Fn", "FnMut", "FnOnce", "AsRef", "AsMut", "From", "Into", "ToOwned", "ToString",
+    "IntoIterator", "DoubleEndedIterator", "ExactSizeIterator", "Extend",
+];
+
+// Whether 'path' names a trait of the standard library, which has no description macro.
+fn names_std(path: &syn::Path) -> bool {
+    if path.segments.len() == 1 {
+        path.leading_colon.is_none() && PRELUDE_TRAITS.iter().any(|name| path.segments[0].ident == name)
+    } else {
+        STD_CRATES.iter().any(|name| path.segments[0].ident == name)
+    }
+}
+
+// Descriptions of the standard library traits that mocks can implement, used in place of the
+// description macros of mocked traits. Returns whether a trait object can still be made
+// of a trait with the bound, which decides if its mock can have a fallback.
+fn std_description(bound: &syn::TraitBound) -> Option<(syn::ItemTrait, bool)> {
+    let ref path = bound.path;
+    let last = path.segments.last().unwrap();
+    // Any other path, like `io::Write` or `Write`, may name a mocked trait of the same name,
+    // so it is left to the description macros.
+    if !names_std(path) {
+        return None;
+    }
+
+    let has_arguments = !last.arguments.is_empty();
+    let description = match &*last.ident.to_string() {
+        "Clone" => (parse_quote! {
+            trait Clone {
+                fn clone(&self) -> Self;
+            }
+        }, false),
+        // Comparing against Self keeps a trait from being made into an object.
+        "PartialEq" => (parse_quote! {
+            trait PartialEq<Rhs = Self> {
+                fn eq(&self, other: &Rhs) -> bool;
+            }
+        }, has_arguments),
+        "Iterator" => (parse_quote! {
+            trait Iterator {
+                type Item;
+                fn next(&mut self) -> Option<Self::Item>;
+            }
+        }, true),
+        "Read" => (parse_quote! {
+            trait Read {
+                fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize>;
+            }
+        }, true),
+        "Write" => (parse_quote! {
+            trait Write {
+                fn write(&mut self, buf: &[u8]) -> ::std::io::Result<usize>;
+                fn flush(&mut self) -> ::std::io::Result<()>;
+            }
+        }, true),
+        _ => return None,
+    };
+
+    Some(description)
+}
+
+// The supertraits of 'trait_block' whose methods are added to its mock. Each is described
+// by a macro emitted along with the supertrait, see 'generate_description'. Standard library
+// traits without a description are left for the user to implement, as they have no macro.
+fn mocked_supertraits(trait_block: &syn::ItemTrait) -> Vec<&syn::TraitBound> {
+    trait_block.supertraits.iter().filter_map(|item| match *item {
+        syn::TypeParamBound::Trait(ref bound) => {
+            let ref ident = bound.path.segments.last().unwrap().ident;
+            let implemented = IMPLEMENTED_SUPERTRAITS.iter().any(|name| ident == name);
+            let undescribed = names_std(&bound.path) && std_description(bound).is_none();
+            if implemented || undescribed || matches!(bound.modifier, syn::TraitBoundModifier::Maybe(_)) {
+                None
+            } else {
+                Some(bound)
+            }
+        },
+        syn::TypeParamBound::Lifetime(_) => None,
+    }).collect()
+}
+
+// The mocked supertraits that are described by a macro, rather than by 'std_description'.
+fn described_supertraits(trait_block: &syn::ItemTrait) -> Vec<&syn::TraitBound> {
+    mocked_supertraits(trait_block).into_iter()
+        .filter(|bound| std_description(bound).is_none())
+        .collect()
+}
+
+// Emits a macro describing a mocked trait, so that the mocks of traits that derive from it
+// can find its methods. The macro is exported under a unique name, and re-exported next to
+// the trait under the trait's own name, so any path that names the trait also names the
+// macro. It appends the trait to its input, and passes everything on to __mock_resolved.
+// Both only exist where the mock does, under 'cfg'.
+fn generate_description(trait_block: &syn::ItemTrait, cfg: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let ref ident = trait_block.ident;
+    let mut description = trait_block.clone();
+    description.attrs.clear();
+    // Exported macros share the crate root, so identical traits in different modules need
+    // different names. The counter is only unique within one build of the crate.
+    let unique_name = concat!(concat!("__mock_describe_", ident, "_"), DESCRIPTIONS.fetch_add(1, Ordering::Relaxed));
+
+    quote! {
+        #[cfg(#cfg)]
+        #[doc(hidden)]
+        #[macro_export]
+        macro_rules! #unique_name {
+            ($($state:tt)*) => {
+                ::mock_derive::__mock_resolved! { $($state)* { #description } }
+            }
+        }
+
+        #[cfg(#cfg)]
+        #[doc(hidden)]
+        #[allow(unused_imports)]
+        pub use #unique_name as #ident;
+    }
+}
+
+// Removes the supertraits named by #[mock(skip(..))] from the trait that is mocked, as they
+// have no description to resolve. The user implements them for the mock instead.
+fn skip_supertraits(trait_block: &mut syn::ItemTrait, skip: &[syn::Path]) -> Result<(), syn::Error> {
+    let unarguments = |path: &syn::Path| {
+        let mut path = path.clone();
+        path.segments.last_mut().unwrap().arguments = syn::PathArguments::None;
+        path.to_token_stream().to_string()
+    };
+
+    for skipped in skip {
+        let name = unarguments(skipped);
+        let before = trait_block.supertraits.len();
+        trait_block.supertraits = trait_block.supertraits.iter()
+            .filter(|bound| match **bound {
+                syn::TypeParamBound::Trait(ref bound) => unarguments(&bound.path) != name,
+                syn::TypeParamBound::Lifetime(_) => true,
+            })
+            .cloned()
+            .collect();
+        if trait_block.supertraits.len() == before {
+            return Err(syn::Error::new_spanned(skipped, format!("`{}` is not a supertrait of `{}`", name, trait_block.ident)));
+        }
+    }
+
+    Ok(())
+}
+
+// Rejects bounds on a bare `Read` or `Write`, which may name either the standard library's
+// trait or a mocked trait imported under that name, and can't be told apart here.
+fn check_io_supertraits(trait_block: &syn::ItemTrait) -> Result<(), syn::Error> {
+    for bound in mocked_supertraits(trait_block) {
+        let ref path = bound.path;
+        let ref ident = path.segments[0].ident;
+        if path.leading_colon.is_none() && path.segments.len() == 1 && (ident == "Read" || ident == "Write") {
+            return Err(syn::Error::new_spanned(path, format!("`{0}` is ambiguous as a supertrait. Write `std::io::{0}` for the standard library's trait, or a path such as `self::{0}` for a mocked trait", ident)));
+        }
+    }
+
+    Ok(())
+}
+
+// Invokes the description macro of the next supertrait that has not been described yet.
+// The state passed along is the attribute of #[mock], the trait, and the descriptions so far.
+fn resolve_next_supertrait(attr_ts: &proc_macro2::TokenStream,
+                           trait_block: &syn::ItemTrait,
+                           descriptions: &[proc_macro2::TokenStream]) -> Option<proc_macro2::TokenStream> {
+    described_supertraits(trait_block).get(descriptions.len()).map(|bound| {
+        let mut path = bound.path.clone();
+        path.segments.last_mut().unwrap().arguments = syn::PathArguments::None;
+        quote! {
+            #path! { { #attr_ts } { #trait_block } #({ #descriptions })* }
+        }
+    })
+}
+
+// Checks that 'bound' gives the generic parameters of the supertrait described by 'base'.
+// The trait may have been renamed on import, so only the parameters can be compared.
+fn check_supertrait(bound: &syn::TraitBound, base: &syn::ItemTrait) -> Result<(), syn::Error> {
+    let ref path = bound.path;
+    let last = path.segments.last().unwrap();
+    let (mut lifetimes, mut types) = (0, 0);
+    if let syn::PathArguments::AngleBracketed(ref args) = last.arguments {
+        for arg in args.args.iter() {
+            match *arg {
+                syn::GenericArgument::Lifetime(_) => lifetimes += 1,
+                syn::GenericArgument::Type(_) => types += 1,
+                _ => {},
+            }
+        }
+    }
+
+    let required = base.generics.type_params().filter(|param| param.default.is_none()).count();
+    let declared = base.generics.type_params().count();
+    if lifetimes > base.generics.lifetimes().count() || types > declared || types < required {
+        let ref generics = base.generics;
+        return Err(syn::Error::new_spanned(path, format!(
+            "`{}` does not match the generic parameters of the mocked trait `{}{}`",
+            quote!{ #path }, base.ident, quote!{ #generics })));
+    }
+
+    for item in base.items.iter() {
+        if let syn::TraitItem::Type(ref assoc) = *item {
+            let bound_given = match last.arguments {
+                syn::PathArguments::AngleBracketed(ref args) => args.args.iter().any(|arg| match *arg {
+                    syn::GenericArgument::Binding(ref binding) => binding.ident == assoc.ident,
+                    _ => false,
+                }),
+                _ => false,
+            };
+
+            if !bound_given {
+                return Err(syn::Error::new_spanned(path, format!(
+                    "The mock of this trait needs the associated type `{}` of `{}` to be given in the bound, as in `{}<{} = ...>`",
+                    assoc.ident, base.ident, base.ident, assoc.ident)));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_trait(trait_block: syn::ItemTrait, supertraits: &[syn::ItemTrait], target: &MockTarget) -> proc_macro2::TokenStream {
+    let ref trait_name = target.static_base;
+    let generics = quote_field!(&trait_block.generics);
+    let (impl_generics, ty_generics, _) = trait_block.generics.split_for_impl();
+    let where_clause = quote_field!(&trait_block.generics.where_clause);
+    let unsafety = quote_field!(&trait_block.unsafety);
+    
+    let ref pubtok = target.vis;
+    let mut derived_additions = proc_macro2::TokenStream::new();
+    
+    let ref impl_name = target.impl_name;
+    let ref mock_method_name = target.mock_method_name;
+
+    let mut impls_sized = false;
+    let mut impls_display = false;
+    for item in trait_block.supertraits.iter() {
+        if let &syn::TypeParamBound::Trait(ref bound) = item {
+            let ref trait_ref = bound.path;
+            let ref ident = trait_ref.segments.last().unwrap().ident;
+            if ident == "Sized" {
+                impls_sized = true;
+            } else if ident == "Display" {
+                impls_display = true;
+            }
+        }
+    }
+
+    // Pair each mocked supertrait with its description, which comes from either the
+    // standard library descriptions or the description macros.
+    let mut descriptions = supertraits.iter();
+    let mut bases = Vec::new();
+    for bound in mocked_supertraits(&trait_block) {
+        let base = match std_description(bound) {
+            Some((base, object_safe)) => {
+                impls_sized = impls_sized || !object_safe;
+                base
+            },
+            None => match descriptions.next() {
+                Some(base) => base.clone(),
+                None => break,
+            },
+        };
+
+        if let Err(err) = check_supertrait(bound, &base) {
+            return err.to_compile_error();
+        }
+
+        bases.push((bound, base));
+    }
+
+    let trait_fns = generate_trait_fns(&trait_block, !impls_sized && target.trait_ref.is_some(), target);
+    let mut mock_impl_methods = trait_fns.mock_impl_methods;
+    let mut fields = trait_fns.fields;
+    let mut ctor = trait_fns.ctor;
+    let method_impls = trait_fns.method_impls;
+    let static_method_setup = trait_fns.static_method_setup;
+    let static_method_impl = trait_fns.static_method_impl;
+    let static_method_body = trait_fns.static_method_body;
+    let static_mocks_ctor = trait_fns.static_mocks_ctor;
+    let static_mocks_def = trait_fns.static_mocks_def;
+    let mut sequence_impls = trait_fns.sequence_impls;
+
+    let self_type: syn::Type = parse_quote! { #impl_name #ty_generics };
+    for (bound, mut impl_body) in bases {
+        let ref trait_ref = bound.path;
+        let (base_predicates, associated_types) = substitute_generics(&mut impl_body, &trait_ref.segments.last().unwrap().arguments, &self_type);
+        // Associated types are given in the impl, rather than in the trait path.
+        let mut trait_ref = trait_ref.clone();
+        if let syn::PathArguments::AngleBracketed(ref mut args) = trait_ref.segments.last_mut().unwrap().arguments {
+            args.args = args.args.iter()
+                .filter(|arg| !matches!(**arg, syn::GenericArgument::Binding(_)))
+                .cloned()
+                .collect();
+        }
+        let base_target = MockTarget {
+            vis: pubtok.clone(),
+            impl_name: impl_name.clone(),
+            mock_method_name: mock_method_name.clone(),
+            trait_ref: Some(quote! { #trait_ref }),
+            control_name: None,
+            constructors: Vec::new(),
+            static_base: quote_field!(&impl_body.ident),
+            storage: Storage::ThreadLocal,
+        };
+        let ret = generate_trait_fns(&impl_body, false, &base_target);
+        let base_mock_impl_methods = ret.mock_impl_methods;
+        let base_fields = ret.fields;
+        let base_ctor = ret.ctor;
+        let base_method_impls = ret.method_impls;
+
+        mock_impl_methods.extend(quote! { #base_mock_impl_methods });
+        fields.extend(quote! { #base_fields });
+        ctor.extend(quote! { #base_ctor });
+        sequence_impls.extend(ret.sequence_impls);
+        let mut base_where_clause = trait_block.generics.where_clause.clone()
+            .unwrap_or_else(|| parse_quote! { where });
+        base_where_clause.predicates.extend(base_predicates);
+        derived_additions.extend(quote! {
+            impl #impl_generics #trait_ref
+                for #impl_name #ty_generics #base_where_clause {
+                #associated_types
+                #base_method_impls
+            }
+        });
+    }
+
+    // A supertrait and its derived trait may store results in the same type.
+    let mut emitted_sequence_impls = Vec::new();
+    sequence_impls.retain(|tokens| {
+        let key = format!("{}", tokens);
+        let first = !emitted_sequence_impls.contains(&key);
+        emitted_sequence_impls.push(key);
+        first
+    });
+
+    fields.extend(quote! { print_string: ::std::sync::Mutex<Option<String>>, });
+    ctor.extend(quote! { print_string : ::std::sync::Mutex::new(None), });
+    
+    // Display is mocked as a method that returns the printed string, and prints the same as
+    // Debug when it has no result.
+    let mut display_impl = proc_macro2::TokenStream::new();
+    if impls_display {
+        let mock_method_ctor = generate_mock_method_ctor(mock_method_name, &FutureKind::Ready);
+        mock_impl_methods.extend(quote! {
+            pub fn method_fmt(&self) -> #mock_method_name <String> {
+                #mock_method_ctor
+            }
+
+            pub fn set_fmt(&mut self, method: #mock_method_name <String>) {
+                self.fmt = Some(method);
+            }
+        });
+        fields.extend(quote! { fmt: Option<#mock_method_name <String>>, });
+        ctor.extend(quote! { fmt: None, });
+        display_impl = quote! {
+            impl #impl_generics ::std::fmt::Display for #impl_name #ty_generics #where_clause {
+                fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                    match self.fmt.as_ref().and_then(|method| method.call()) {
+                        Some(message) => f.pad(&message),
+                        None => ::std::fmt::Debug::fmt(self, f),
+                    }
+                }
+            }
+        };
+    }
+
+    // The mock of an inherent impl wraps the struct that holds its expectations.
+    let control_name = target.control_name.as_ref().unwrap_or(impl_name);
+    let init = match target.control_name {
+        Some(_) => quote! { #impl_name(#control_name { #ctor }) },
+        None => quote! { #impl_name { #ctor } },
+    };
+    let mut constructors = proc_macro2::TokenStream::new();
+    if !target.constructors.iter().any(|sig| sig.ident == "new") {
+        constructors.extend(quote! {
+            pub fn new() -> #impl_name #ty_generics {
+                #init
+            }
+        });
+    }
+    for sig in &target.constructors {
+        let ident = &sig.ident;
+        let ref method_generics = sig.generics;
+        let ref method_where_clause = sig.generics.where_clause;
+        let inputs = &sig.inputs;
+        // The arguments of a constructor are ignored, the mock is set up through its methods.
+        constructors.extend(quote! {
+            #[allow(unused_variables)]
+            pub fn #ident #method_generics (#inputs) -> #impl_name #ty_generics #method_where_clause {
+                #init
+            }
+        });
+    }
+
+    let method_impl = match target.trait_ref {
+        Some(ref trait_ref) => quote! {
+            #unsafety impl #impl_generics #trait_ref for #impl_name #ty_generics #where_clause {
+                #method_impls
+                #static_method_impl
+            }
+        },
+        None => quote! {
+            #[allow(dead_code)]
+            impl #impl_generics #impl_name #ty_generics #where_clause {
+                #method_impls
+                #static_method_impl
+            }
+        },
+    };
+
+    let mock_method_body = generate_mock_method_body(pubtok, mock_method_name, &quote! {});
+    let static_struct_name = concat!("STATIC__", trait_name);
+    let mut static_content = quote!{ };
+    let mut static_scope = quote!{ };
+    if format!("{}", static_mocks_def).len() > 0 {
+        let static_name = generate_static_name(trait_name);
+        let static_init = quote!{
+            #static_struct_name { #static_mocks_ctor }
+        };
+        let mut_static = make_mut_static(&static_name, &static_struct_name, &static_init, target.storage);
+        let guard_name = concat!(impl_name, "Guard");
+        let scope_guard = generate_scope_guard(pubtok, &guard_name);
+        static_scope = generate_scope(&quote! { scope_statics }, &guard_name, &static_name, &static_init, &quote! {});
+        if target.storage == Storage::Global {
+            let lock_name = concat!("__lock_", static_name);
+            static_scope = quote! {
+                #static_scope
+
+                /// Held by a test for as long as it uses the static methods of the mock, which
+                /// start out with no expectations. Tests that don't hold it share the mocks with
+                /// every other test running at the same time.
+                pub fn lock_statics() -> ::std::sync::MutexGuard<'static, ()> {
+                    #lock_name()
+                }
+            };
+        }
+
+        static_content = quote! {
+            #[allow(non_camel_case_types)]
+            struct #static_struct_name {
+                #static_mocks_def
+            }
+            
+            #mut_static
+
+            #scope_guard
+
+            #static_method_body
+        };
+    }
+
+    let mut wrapper = proc_macro2::TokenStream::new();
+    if target.control_name.is_some() {
+        wrapper = quote! {
+            #[allow(dead_code)]
+            #pubtok struct #impl_name #generics (#control_name #ty_generics) #where_clause;
+
+            impl #impl_generics ::std::ops::Deref for #impl_name #ty_generics #where_clause {
+                type Target = #control_name #ty_generics;
+
+                fn deref(&self) -> &Self::Target {
+                    &self.0
+                }
+            }
+
+            impl #impl_generics ::std::ops::DerefMut for #impl_name #ty_generics #where_clause {
+                fn deref_mut(&mut self) -> &mut Self::Target {
+                    &mut self.0
+                }
+            }
+
+            impl #impl_generics ::std::fmt::Debug for #impl_name #ty_generics #where_clause {
+                fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                    ::std::fmt::Debug::fmt(&self.0, f)
+                }
+            }
+
+            #[allow(dead_code)]
+            impl #impl_generics #impl_name #ty_generics #where_clause {
+                #constructors
+            }
+        };
+        constructors = proc_macro2::TokenStream::new();
+    }
+
+    let stream = quote! {
+        #static_content
+
+        #[allow(dead_code)]
+        #pubtok struct #control_name #generics #where_clause {
+            #fields
+        }
+
+        #wrapper
+
+        #[allow(dead_code)]
+        #[allow(non_camel_case_types)]
+        impl #impl_generics ::std::fmt::Debug for #control_name #ty_generics #where_clause {
+            fn fmt(&self, f: &mut ::std::fmt::Formatter<'_>) -> ::std::fmt::Result {
+                // panic!("Printing mocks is not supported. Do not call println on mocks.");
+                let print_string = self.print_string.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                let mut message = "This is the default message for a mock object. You can set the print behavior via a call to 'set_print_string'";
+                if print_string.as_ref().is_some() {
+                    message = print_string.as_ref().unwrap();
+                }
+
+                f.pad(&message)
+            }
+        }
+
+        #display_impl
+
+        // Your mocks may not use all of these functions, so it's fine to allow
+        // dead code in this impl block.
+        #[allow(dead_code)]
+        impl #impl_generics #control_name #ty_generics #where_clause {
+            #mock_impl_methods
+            #static_method_setup
+            #static_scope
+
+            #constructors
+
+            pub fn set_print_string(&self, arg_string: String) {
+                let mut print_string = self.print_string.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                *print_string = Some(arg_string);
+            }
+        }
+
+        #mock_method_body
+
+        #(#sequence_impls)*
+
+        #method_impl
+
+
+        #derived_additions
+    };
+
+    stream
+}
+
+// Mocks the methods of an inherent impl with a struct of their own, which can take the
+// place of the original struct in tests through a use declaration with #[mock].
+fn parse_impl(impl_block: syn::ItemImpl, args: &MockArgs) -> proc_macro2::TokenStream {
+    let ident = match *impl_block.self_ty {
+        syn::Type::Path(ref type_path) if type_path.qself.is_none() => type_path.path.segments.last().unwrap().ident.clone(),
+        _ => return syn::Error::new_spanned(&impl_block.self_ty, "#[mock] can only be applied to the impl of a named type").to_compile_error(),
+    };
+
+    let mut items = Vec::new();
+    let mut constructors = Vec::new();
+    let mut consts = Vec::new();
+    let mut self_returns = Vec::new();
+    for item in &impl_block.items {
+        match *item {
+            syn::ImplItem::Method(ref method) => {
+                let returns_self = match method.sig.output {
+                    syn::ReturnType::Type(_, ref ty) => match **ty {
+                        syn::Type::Path(ref type_path) => {
+                            let last = &type_path.path.segments.last().unwrap().ident;
+                            type_path.path.segments.len() == 1 && (last == "Self" || *last == ident)
+                        },
+                        _ => false,
+                    },
+                    syn::ReturnType::Default => false,
+                };
+
+                if returns_self && !parse_args(method.sig.inputs.iter()).is_instance_method {
+                    constructors.push(method.sig.clone());
+                } else {
+                    if returns_self {
+                        self_returns.push(items.len());
+                    }
+                    items.push(syn::TraitItem::Method(syn::TraitItemMethod {
+                        attrs: Vec::new(),
+                        sig: method.sig.clone(),
+                        default: None,
+                        semi_token: Some(Default::default()),
+                    }));
+                }
+            },
+            syn::ImplItem::Const(ref constant) => consts.push(constant.clone()),
+            _ => return syn::Error::new_spanned(item, "#[mock] only supports methods and constants in impl blocks").to_compile_error(),
+        }
+    }
+
+    let trait_block = syn::ItemTrait {
+        attrs: Vec::new(),
+        vis: parse_quote! { pub(crate) },
+        unsafety: None,
+        auto_token: None,
+        trait_token: Default::default(),
+        ident: ident,
+        generics: impl_block.generics.clone(),
+        colon_token: None,
+        supertraits: syn::punctuated::Punctuated::new(),
+        brace_token: Default::default(),
+        items: items,
+    };
+
+    let mut target = MockTarget::for_trait(&trait_block, args);
+    target.trait_ref = None;
+    target.control_name = Some(concat!(target.impl_name, "Control"));
+    target.constructors = constructors;
+
+    // Methods that return Self, such as those of a builder, return the mock. The results are
+    // stored in the control type, where Self would name the control type instead.
+    let mut trait_block = trait_block;
+    let ref impl_name = target.impl_name;
+    let (_, ty_generics, _) = impl_block.generics.split_for_impl();
+    for index in self_returns {
+        if let syn::TraitItem::Method(ref mut method) = trait_block.items[index] {
+            method.sig.output = parse_quote! { -> #impl_name #ty_generics };
+        }
+    }
+
+    let mut consts_impl = proc_macro2::TokenStream::new();
+    if !consts.is_empty() {
+        let ref impl_name = target.impl_name;
+        let (impl_generics, ty_generics, where_clause) = impl_block.generics.split_for_impl();
+        consts_impl = quote! {
+            impl #impl_generics #impl_name #ty_generics #where_clause {
+                #(#consts)*
+            }
+        };
+    }
+
+    let mock = parse_trait(trait_block, &[], &target);
+    quote! {
+        #mock
+        #consts_impl
+    }
+}
+
+// Replaces the names imported by a use declaration with the names of their mocks.
+fn swap_use(tree: &syn::UseTree, args: &MockArgs) -> Result<syn::UseTree, syn::Error> {
+    Ok(match *tree {
+        syn::UseTree::Path(ref path) => {
+            let mut path = path.clone();
+            *path.tree = swap_use(&path.tree, args)?;
+            syn::UseTree::Path(path)
+        },
+        syn::UseTree::Name(ref name) if name.ident == "self" => tree.clone(),
+        syn::UseTree::Name(ref name) => mock_use(&name.ident, &name.ident, args),
+        syn::UseTree::Rename(ref rename) => mock_use(&rename.ident, &rename.rename, args),
+        syn::UseTree::Group(ref group) => {
+            let mut group = group.clone();
+            for item in group.items.iter_mut() {
+                *item = swap_use(item, args)?;
+            }
+            syn::UseTree::Group(group)
+        },
+        syn::UseTree::Glob(_) => return Err(syn::Error::new_spanned(tree, "#[mock] can't swap the items of a glob import for mocks")),
+    })
+}
+
+fn mock_use(ident: &syn::Ident, rename: &syn::Ident, args: &MockArgs) -> syn::UseTree {
+    let mock = match args.name {
+        Some(ref name) => name.clone(),
+        None => syn::Ident::new(&format!("Mock{}", ident), ident.span()),
+    };
+    parse_quote! { #mock as #rename }
+}
+
+// The parts of the mock of a function with a body. They are gathered into a controller
+// by 'generate_fn_controller'.
+struct FnMock {
+    field: proc_macro2::TokenStream,
+    ctor: proc_macro2::TokenStream,
+    controller_methods: proc_macro2::TokenStream,
+    mock_method_body: proc_macro2::TokenStream,
+    function: proc_macro2::TokenStream,
+}
+
+fn camel_case(name: &str) -> String {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word[..1].to_uppercase() + &word[1..])
+        .collect()
+}
+
+// A mocked function keeps its name, and is controlled through the singleton 'static_name'.
+// When no mock method is set, the original body is called.
+fn generate_fn_mock(fn_item: &syn::ItemFn, controller: &proc_macro2::TokenStream, static_name: &proc_macro2::TokenStream, storage: Storage) -> Result<FnMock, syn::Error> {
+    let ref sig = fn_item.sig;
+    if sig.generics.type_params().count() > 0 || sig.generics.const_params().count() > 0 {
+        return Err(syn::Error::new_spanned(&sig.generics, "Mocking functions with generic parameters is not supported"));
+    }
+
+    if let Some(ref constness) = sig.constness {
+        return Err(syn::Error::new_spanned(constness, "Mocking const functions is not supported"));
+    }
+
+    let base_name = quote_field!(&sig.ident);
+    let name = concat!("Method_", base_name);
+    let name_lc = concat!("method_", base_name);
+    let setter_name = concat!("set_", base_name);
+    let clear_name = concat!("clear_", base_name);
+    let scoped_name = concat!("scoped_", base_name);
+    let original_name = concat!("__mock_original_", base_name);
+
+    let fn_args = parse_args(sig.inputs.iter());
+    let ref args_with_types = fn_args.args_with_types;
+    let ref args_with_no_self_no_types = fn_args.args_with_no_self_no_types;
+    let (no_return, return_type) = parse_return_type(&sig.output);
+    let (future_kind, value_output) = parse_future_kind(sig);
+    let (result_type, return_kind) = parse_result_type(&value_output, &sig.generics, &fn_args, &future_kind);
+    if return_kind != ReturnKind::Owned {
+        return Err(syn::Error::new_spanned(&sig.output, "Mocking functions that return references is not supported"));
+    }
+
+    let arg_returns = generate_arg_returns(&fn_args, &value_output, &return_kind, &future_kind);
+    let (return_statement,
+         retval_statement,
+         some_arg) = make_return_tokens(no_return, &return_type, &return_kind, &future_kind);
+    let call_original = match sig.asyncness {
+        Some(_) => quote! { #original_name(#args_with_no_self_no_types).await },
+        None => quote! { #original_name(#args_with_no_self_no_types) },
+    };
+    let fn_body = generate_fn_body(&quote! {
+                                       let value = #static_name();
+                                       let singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                                   },
+                                   &quote! { singleton.#name_lc.as_ref() },
+                                   &future_kind,
+                                   (&some_arg, &retval_statement),
+                                   &arg_returns,
+                                   &quote! { panic!("Called a mocked function without a result set."); },
+                                   // Another thread may have cleared a global mock since it was checked.
+                                   &quote! {
+                                       drop(singleton);
+                                       return #call_original;
+                                   });
+
+    let mut original_sig = sig.clone();
+    original_sig.ident = syn::Ident::new(&original_name.to_string(), sig.ident.span());
+    let ref block = fn_item.block;
+
+    let ref attrs = fn_item.attrs;
+    let ref vis = fn_item.vis;
+    let asyncness = quote_field!(&sig.asyncness);
+    let unsafety = quote_field!(&sig.unsafety);
+    let abi = quote_field!(&sig.abi);
+    let ref fn_generics = sig.generics;
+    let ref fn_where_clause = sig.generics.where_clause;
+    let mock_method_ctor = generate_mock_method_ctor(&name, &future_kind);
+    let scoped_setter = generate_scoped_setter(&scoped_name,
+                                               &concat!(controller, "Guard"),
+                                               static_name,
+                                               &name_lc,
+                                               &quote! { #name<#result_type> },
+                                               &quote! {});
+
+    Ok(FnMock {
+        field: quote! { #name_lc: Option<#name<#result_type>>, },
+        ctor: quote! { #name_lc: None, },
+        controller_methods: quote! {
+            #[allow(dead_code)]
+            pub fn #name_lc() -> #name<#result_type> {
+                #mock_method_ctor
+            }
+
+            #[allow(dead_code)]
+            pub fn #setter_name(x: #name<#result_type>) {
+                let value = #static_name();
+                let mut singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                singleton.#name_lc = Some(x);
+            }
+
+            #[allow(dead_code)]
+            pub fn #clear_name() {
+                let value = #static_name();
+                let mut singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                singleton.#name_lc = None;
+            }
+
+            #scoped_setter
+        },
+        mock_method_body: generate_mock_method_body(&pub_token!(), &name, &storage.send_bound()),
+        function: quote! {
+            #(#attrs)*
+            #vis #unsafety #asyncness #abi fn #base_name #fn_generics (#args_with_types) #return_statement #fn_where_clause {
+                #original_sig #block
+
+                // The lock is released before the original body runs, as it may call this function.
+                // The mock is checked again under the lock below.
+                let is_set = {
+                    let value = #static_name();
+                    let singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                    singleton.#name_lc.is_some()
+                };
+                if !is_set {
+                    return #call_original;
+                }
+
+                #fn_body
+            }
+        },
+    })
+}
+
+fn generate_fn_controller(controller_vis: &proc_macro2::TokenStream,
+                          controller: &proc_macro2::TokenStream,
+                          static_name: &proc_macro2::TokenStream,
+                          mocks: &[FnMock],
+                          storage: Storage) -> proc_macro2::TokenStream {
+    let fields = mocks.iter().map(|mock| &mock.field);
+    let ctor = mocks.iter().map(|mock| &mock.ctor);
+    let controller_methods = mocks.iter().map(|mock| &mock.controller_methods);
+    let mock_method_bodies = mocks.iter().map(|mock| &mock.mock_method_body);
+    let init_body = quote! {
+        #controller { #(#ctor)* }
+    };
+    let external_static = make_mut_static(static_name, controller, &init_body, storage);
+    let guard_name = concat!(controller, "Guard");
+    let scope_guard = generate_scope_guard(controller_vis, &guard_name);
+    let scope = generate_scope(&quote! { scope }, &guard_name, static_name, &init_body, &quote! {});
+    let mut lock = proc_macro2::TokenStream::new();
+    if storage == Storage::Global {
+        let lock_name = concat!("__lock_", static_name);
+        lock = quote! {
+            /// Held by a test for as long as it uses these mocks, which start out with no
+            /// expectations. Tests that don't hold it share the mocks with every other test
+            /// running at the same time.
+            pub fn lock() -> ::std::sync::MutexGuard<'static, ()> {
+                #lock_name()
+            }
+        };
+    }
+
+    quote! {
+        #[allow(dead_code)]
+        #controller_vis struct #controller {
+            #(#fields)*
+        }
+
+        #[allow(dead_code)]
+        #external_static
+
+        #scope_guard
+
+        #(#mock_method_bodies)*
+
+        impl #controller {
+            #(#controller_methods)*
+            #scope
+            #lock
+        }
+    }
+}
+
+// A mocked function is controlled through Mock<CamelCaseName>.
+fn parse_fn(fn_item: syn::ItemFn, args: &MockArgs) -> proc_macro2::TokenStream {
+    let controller = match args.name {
+        Some(ref name) => quote! { #name },
+        None => concat!("Mock", camel_case(&fn_item.sig.ident.to_string())),
+    };
+    let controller_vis = match args.vis {
+        Some(ref vis) => quote! { #vis },
+        None => quote_field!(&fn_item.vis),
+    };
+    let static_name = concat!("Static", controller);
+
+    match generate_fn_mock(&fn_item, &controller, &static_name, args.storage()) {
+        Ok(mock) => {
+            let function = mock.function.clone();
+            let mock_controller = generate_fn_controller(&controller_vis, &controller, &static_name, &[mock], args.storage());
+            quote! {
+                #mock_controller
+                #function
+            }
+        },
+        Err(err) => err.to_compile_error(),
+    }
+}
+
+// Every function of a mocked module is controlled through a single Mock<CamelCaseName>,
+// which is declared in the module, so that it can name the types of the module.
+fn parse_mod(mut mod_item: syn::ItemMod, args: &MockArgs) -> proc_macro2::TokenStream {
+    let (brace, items) = match mod_item.content {
+        Some(ref content) => content.clone(),
+        None => return syn::Error::new_spanned(&mod_item, "#[mock] can only be applied to a module with a body").to_compile_error(),
+    };
+
+    let controller = match args.name {
+        Some(ref name) => quote! { #name },
+        None => concat!("Mock", camel_case(&mod_item.ident.to_string())),
+    };
+    let static_name = concat!("Static", controller);
+    let (controller_vis, export_vis) = match (&args.vis, &mod_item.vis) {
+        (&Some(ref vis), _) => (quote! { #vis }, quote! { #vis }),
+        (&None, &syn::Visibility::Inherited) => (quote! { pub(super) }, proc_macro2::TokenStream::new()),
+        (&None, vis) => (quote! { #vis }, quote! { #vis }),
+    };
+
+    // Functions that can't be mocked, such as generic functions, are reported, unless they
+    // are marked with #[mock(unmocked)] to leave them as they are.
+    let mut mocks = Vec::new();
+    let mut content = Vec::new();
+    let mut errors: Option<syn::Error> = None;
+    for mut item in items {
+        if let syn::Item::Fn(ref mut fn_item) = item {
+            let result = match unmocked(&fn_item.attrs) {
+                Ok(true) => {
+                    fn_item.attrs.retain(|attr| !attr.path.is_ident("mock"));
+                    None
+                },
+                Ok(false) => Some(generate_fn_mock(fn_item, &controller, &static_name, args.storage()).map_err(|mut err| {
+                    err.combine(syn::Error::new_spanned(&fn_item.sig.ident, "Mark the function with #[mock(unmocked)] to leave it as it is"));
+                    err
+                })),
+                Err(err) => Some(Err(err)),
+            };
+            match result {
+                Some(Ok(mock)) => {
+                    content.push(syn::Item::Verbatim(mock.function.clone()));
+                    mocks.push(mock);
+                    continue;
+                },
+                Some(Err(err)) => match errors {
+                    Some(ref mut errors) => errors.combine(err),
+                    None => errors = Some(err),
+                },
+                None => {},
+            }
+        }
+        content.push(item);
+    }
+
+    if let Some(errors) = errors {
+        return errors.to_compile_error();
+    }
+
+    content.push(syn::Item::Verbatim(generate_fn_controller(&controller_vis, &controller, &static_name, &mocks, args.storage())));
+    mod_item.content = Some((brace, content));
+    let ref mod_name = mod_item.ident;
+    quote! {
+        #mod_item
+
+        #export_vis use self::#mod_name::#controller;
+    }
+}
+
+fn parse_foreign_functions(func_block: syn::ItemForeignMod, _raw_block: &syn::Item, args: &MockArgs) -> proc_macro2::TokenStream {
+    let mut result = proc_macro2::TokenStream::new();
+    let mut extern_mocks_ctor_args = proc_macro2::TokenStream::new();
+    let mut extern_mocks_def = proc_macro2::TokenStream::new();
+    let mut stored_types = Vec::new();
+
+    let abi;
+    if let Some(ref name) = func_block.abi.name {
+        abi = quote!{ extern #name };
+    } else {
+        abi = quote!{ extern };
+    }
+
+    // Mocks that opt into unwinding keep the calling convention of the block.
+    let unwind_abi = match func_block.abi.name {
+        Some(ref name) if name.value() == "C" || name.value() == "system" => {
+            let name = syn::LitStr::new(&format!("{}-unwind", name.value()), name.span());
+            quote!{ extern #name }
+        },
+        Some(_) => abi.clone(),
+        None => quote!{ extern "C-unwind" },
+    };
+    
+    let extern_name = extern_mock_name(&func_block, args);
+    let extern_vis = match args.vis {
+        Some(ref vis) => quote! { #vis },
+        None => pub_token!(),
+    };
+    let handles_name = concat!("Handles", extern_name);
+    let static_name = concat!("Static", extern_name);
+    let guard_name = concat!(extern_name, "Guard");
+    for item in func_block.items {
+        match item {
+            syn::ForeignItem::Fn(ref fn_item) => {
+                let ref decl = fn_item.sig;
+                let ref generics = decl.generics;
+
+                if generics.type_params().count() > 0 || generics.lifetimes().count() > 0 {
+                    panic!("Mocking extern functions with generics/lifetimes not yet supported.");
+                }
+
+                let fn_args = parse_args(decl.inputs.iter());
+                let ref args_with_types = fn_args.args_with_types;
+                let (no_return, return_type) = parse_return_type(&decl.output);
+                let arg_returns = generate_arg_returns(&fn_args, &decl.output, &ReturnKind::Owned, &FutureKind::Ready);
+
+                // Handles passed to any function must still be open, and functions marked with
+                // #[mock(closes_handle)] close the handle given as their first pointer.
+                let closes_handle = match closes_handle(&fn_item.attrs) {
+                    Ok(closes_handle) => closes_handle,
+                    Err(err) => return err.to_compile_error(),
+                };
+                let pointers: Vec<_> = fn_args.typed_args.iter()
+                    .filter(|&&(_, ref ty)| matches!(*ty, syn::Type::Ptr(_)))
+                    .map(|&(ref tok, _)| tok)
+                    .collect();
+                let mut handle_checks = proc_macro2::TokenStream::new();
+                if closes_handle {
+                    match pointers.first() {
+                        Some(handle) => handle_checks.extend(quote! {
+                            #extern_name::close_handle(#handle);
+                        }),
+                        None => return syn::Error::new_spanned(&decl.inputs, "A function that closes a handle must take the handle as a pointer").to_compile_error(),
+                    }
+                }
+                for handle in pointers.iter().skip(if closes_handle { 1 } else { 0 }) {
+                    handle_checks.extend(quote! {
+                        #extern_name::check_handle(#handle as usize);
+                    });
+                }
+                
+                let base_name = quote_field!(&decl.ident);
+                let name = concat!("Method_", base_name);
+                let name_lc = concat!("method_", base_name);
+                let setter_name = concat!("set_", base_name);
+                let clear_name = concat!("clear_", base_name);
+                extern_mocks_ctor_args = quote!{ #extern_mocks_ctor_args #name_lc: None, };
+                extern_mocks_def = quote!{ #extern_mocks_def #name_lc: Option<#name<#return_type>>, };
+                if let syn::ReturnType::Type(_, ref ty) = decl.output {
+                    stored_types.push((**ty).clone());
+                }
+                let pubtok = quote_field!(&fn_item.vis);                
+                let (return_statement,
+                     retval_statement,
+                     some_arg) = make_return_tokens(no_return, &return_type, &ReturnKind::Owned, &FutureKind::Ready);
+                let fn_body = generate_fn_body(&quote! {
+                                                   let value = #static_name();
+                                                   let singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                                               },
+                                               &quote! { singleton.#name_lc.as_ref() },
+                                               &FutureKind::Ready,
+                                               (&some_arg, &retval_statement),
+                                               &arg_returns,
+                                               &quote! { panic!("Called a static mock function without a value set."); },
+                                               &quote! { panic!(); });
+                // Hardcode pub to true here, so
+                // that other modules can universally use Extern<>Mocks
+                let mock_method_body = generate_mock_method_body(&pub_token!(),
+                                                                 &quote!{ #name },
+                                                                 &args.storage().send_bound());
+                let mock_method_ctor = generate_mock_method_ctor(&name, &FutureKind::Ready);
+                let fn_name = base_name.to_string();
+                let mut shim_body = quote! {
+                    #handle_checks
+                    #fn_body
+                };
+                if args.records_failures() {
+                    let fallback_value = fallback_value(&decl.output);
+                    shim_body = generate_recorded_call(&shim_body, &quote! {
+                        let value = #static_name();
+                        let mut singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                        singleton.failures.push((#fn_name, failure));
+                        match singleton.#name_lc.as_ref().and_then(|method| method.fallback.as_ref()) {
+                            Some(fallback) => fallback(),
+                            None => #fallback_value,
+                        }
+                    });
+                }
+                let scoped_setter = generate_scoped_setter(&concat!("scoped_", base_name),
+                                                           &guard_name,
+                                                           &static_name,
+                                                           &name_lc,
+                                                           &quote! { #name<#return_type> },
+                                                           &quote! {
+                                                               #extern_name::raise_failures(&#extern_name::take_failures(Some(#fn_name)));
+                                                           });
+                result = quote! {
+                    #result
+                    #mock_method_body
+
+                    impl #extern_name {
+                        #[allow(dead_code)]
+                        pub fn #name_lc() -> #name<#return_type> {
+                            #mock_method_ctor
+                        }
+
+                        #[allow(dead_code)]
+                        pub fn #setter_name (x: #name<#return_type>) {
+                            let value = #static_name();
+                            let mut singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                            singleton.#name_lc = Some(x);
+                        }
+
+                        #[allow(dead_code)]
+                        pub fn #clear_name () {
+                            let value = #static_name();
+                            let mut singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                            singleton.#name_lc = None;
+                        }
+
+                        #scoped_setter
+                    }
+                };
+
+                let mut shim_abi = if args.unwinds() { unwind_abi.clone() } else { abi.clone() };
+                // Stable Rust can't define variadic functions. Instead, the declaration is kept
+                // and linked to a shim that only takes the fixed arguments, so the variadic
+                // arguments are ignored. This relies on the fixed arguments being passed the same
+                // way to a variadic function as to the shim, which holds for integers and pointers
+                // on the common targets, but is not guaranteed by every ABI. The shim keeps the
+                // ABI of the declaration, so it never unwinds.
+                let mut shim_name = base_name.clone();
+                if decl.variadic.is_some() {
+                    shim_abi = abi.clone();
+                    shim_name = concat!("__mock_variadic_", base_name);
+                    let link_name = shim_name.to_string();
+                    let mut declaration = fn_item.clone();
+                    declaration.attrs.retain(|attr| !attr.path.is_ident("link_name") && !attr.path.is_ident("mock"));
+                    result = quote! {
+                        #result
+
+                        #abi {
+                            #[link_name = #link_name]
+                            #declaration
+                        }
+                    };
+                }
+
+                result = quote! {
+                    #result
 
                     // We can assume unsafe due to this being an extern block.
                     #[allow(unused_variables)]
                     #[allow(dead_code)]
-                    #[allow(private_no_mangle_fns)]
+                    #[allow(private_no_mangle_fns)]
+                    #[no_mangle]
+                    #pubtok unsafe #shim_abi fn #shim_name (#args_with_types) #return_statement {
+                        #shim_body
+                    }
+                };
+            },
+            syn::ForeignItem::Static(ref static_item) => {
+                // The static is replaced with a zeroed static mut of our own, as C globals
+                // start zeroed as well. Values are leaked rather than dropped when replaced.
+                let ref attrs = static_item.attrs;
+                let ref vis = static_item.vis;
+                let ref ident = static_item.ident;
+                let ref ty = static_item.ty;
+                if !can_be_zeroed(ty) {
+                    return syn::Error::new_spanned(ty, "Mocked statics start zeroed, which is not a valid value of this type. Use a raw pointer or an Option instead")
+                        .to_compile_error();
+                }
+                let setter_name = concat!("set_", ident);
+                let getter_name = concat!("get_", ident);
+                let setter_doc = format!("Writes `{}`, leaking its previous value.\n\n\
+                    # Safety\n\n\
+                    Nothing may read or write the static at the same time, on any thread, \
+                    and no reference returned by `{}` may be in use.", ident, getter_name);
+                let getter_doc = format!("Borrows the value of `{}`.\n\n\
+                    # Safety\n\n\
+                    The static must not be written, through `{}` or otherwise, \
+                    while the reference is in use.", ident, setter_name);
+                result = quote! {
+                    #result
+
+                    impl #extern_name {
+                        #[doc = #setter_doc]
+                        #[allow(dead_code)]
+                        pub unsafe fn #setter_name(value: #ty) {
+                            ::std::ptr::write(::std::ptr::addr_of_mut!(#ident), value);
+                        }
+
+                        #[doc = #getter_doc]
+                        #[allow(dead_code)]
+                        pub unsafe fn #getter_name() -> &'static #ty {
+                            &*::std::ptr::addr_of!(#ident)
+                        }
+                    }
+
+                    #(#attrs)*
+                    #[allow(non_upper_case_globals)]
+                    #[allow(dead_code)]
+                    #[no_mangle]
+                    #vis static mut #ident: #ty = unsafe { ::std::mem::zeroed() };
+                }
+            },
+            syn::ForeignItem::Type(ref type_item) => {
+                // Opaque types are only ever used behind pointers, such as those handed out
+                // by 'new_handle'.
+                let ref attrs = type_item.attrs;
+                let ref vis = type_item.vis;
+                let ref ident = type_item.ident;
+                result = quote! {
+                    #result
+
+                    #(#attrs)*
+                    #[allow(non_camel_case_types)]
+                    #[allow(dead_code)]
+                    #[repr(C)]
+                    #vis struct #ident {
+                        _private: [u8; 0],
+                    }
+                }
+            },
+            syn::ForeignItem::Macro(_) => {
+                panic!("Mocking macros not supported.");
+            },
+            syn::ForeignItem::Verbatim(_) => {
+                panic!("Mocking not supported for unparsed items.");
+            },
+            _ => {
+                panic!("Mocking not support for this variant.");
+            }
+        }
+    }
+
+    // Blocks of statics alone have no mock methods to keep.
+    let mut external_static = proc_macro2::TokenStream::new();
+    let mut reset_mocks = proc_macro2::TokenStream::new();
+    let mut scope = proc_macro2::TokenStream::new();
+    if !extern_mocks_ctor_args.is_empty() {
+        extern_mocks_def.extend(quote!{ failures: Vec<(&'static str, String)>, });
+        extern_mocks_ctor_args.extend(quote!{ failures: Vec::new(), });
+        let init_body = quote!{
+            #extern_name { #extern_mocks_ctor_args }
+        };
+        external_static = make_mut_static(&quote! { #static_name }, &quote! { #extern_name }, &init_body, args.storage());
+        let reset_name = concat!("__reset_", static_name);
+        reset_mocks = quote! { #reset_name(); };
+        let scope_fn = generate_scope(&quote! { scope }, &guard_name, &static_name, &init_body, &quote! {
+            #extern_name::raise_failures(&scoped.failures);
+        });
+        let scope_guard = generate_scope_guard(&extern_vis, &guard_name);
+        scope = quote! {
+            #scope_guard
+
+            #[allow(dead_code)]
+            impl #extern_name {
+                #scope_fn
+
+                // Panics with the failures recorded by the mocks, and forgets them.
+                pub fn verify() {
+                    Self::raise_failures(&Self::take_failures(None));
+                }
+
+                // Takes the failures of the function 'name', or of every function.
+                fn take_failures(name: Option<&str>) -> Vec<(&'static str, String)> {
+                    let value = #static_name();
+                    let mut singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                    let (taken, kept) = ::std::mem::take(&mut singleton.failures)
+                        .into_iter()
+                        .partition(|failure| name.map_or(true, |name| failure.0 == name));
+                    singleton.failures = kept;
+                    taken
+                }
+
+                fn raise_failures(failures: &[(&'static str, String)]) {
+                    if failures.is_empty() {
+                        return;
+                    }
+
+                    let message = failures.iter()
+                        .map(|failure| format!("{}: {}", failure.0, failure.1))
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    if ::std::thread::panicking() {
+                        eprintln!("Extern mocks recorded failures:\n{}", message);
+                    } else {
+                        panic!("Extern mocks recorded failures:\n{}", message);
+                    }
+                }
+            }
+        };
+    }
+    let mut lock = proc_macro2::TokenStream::new();
+    if args.storage() == Storage::Global {
+        let handles_lock_name = concat!("__lock_", handles_name);
+        let send_check = assert_send_unless_pointer(stored_types.iter());
+        lock = quote! {
+            // Sound once the stored types are checked, see 'assert_send_unless_pointer'.
+            #send_check
+            unsafe impl Send for #extern_name {}
+
+            #[allow(dead_code)]
+            impl #extern_name {
+                /// Held by a test for as long as it uses these mocks, which start out with no
+                /// expectations and no open handles. Tests that don't hold it share the mocks
+                /// with every other test running at the same time.
+                pub fn lock() -> ::std::sync::MutexGuard<'static, ()> {
+                    let guard = #handles_lock_name();
+                    #reset_mocks
+                    guard
+                }
+            }
+        };
+    }
+    let handles_static = make_mut_static(&handles_name, &quote! { ::std::collections::HashMap<usize, bool> }, &quote! {
+        ::std::collections::HashMap::new()
+    }, args.storage());
+    result = quote!{
+        #[allow(dead_code)]
+        #[allow(unused_variables)]
+        #extern_vis struct #extern_name {
+            #extern_mocks_def
+        }
+        
+        #[allow(dead_code)]
+        #[allow(unused_variables)]
+        #external_static
+
+        #[allow(dead_code)]
+        #handles_static
+
+        #scope
+
+        #lock
+
+        // Fake handles for the opaque types of C libraries. Each handle is a distinct
+        // allocation, which is never freed, so that it can't be mistaken for another.
+        #[allow(dead_code)]
+        impl #extern_name {
+            pub fn new_handle<T>() -> *mut T {
+                let handle = ::std::boxed::Box::into_raw(::std::boxed::Box::new(0u64)) as *mut T;
+                #handles_name().inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner).insert(handle as usize, true);
+                handle
+            }
+
+            pub fn is_open_handle<T>(handle: *const T) -> bool {
+                #handles_name().inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner).get(&(handle as usize)) == Some(&true)
+            }
+
+            pub fn open_handles() -> usize {
+                #handles_name().inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner).values().filter(|open| **open).count()
+            }
+
+            pub fn close_handle<T>(handle: *const T) {
+                match #handles_name().inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner).get_mut(&(handle as usize)) {
+                    Some(open) if *open => *open = false,
+                    Some(_) => panic!("The handle {:?} was closed more than once.", handle),
+                    None => panic!("Closed the handle {:?}, which was not created by new_handle.", handle),
+                }
+            }
+
+            pub fn verify_handles() {
+                let open = Self::open_handles();
+                if open > 0 {
+                    panic!("{} handle(s) created by new_handle were never closed.", open);
+                }
+            }
+
+            fn check_handle(address: usize) {
+                if #handles_name().inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner).get(&address) == Some(&false) {
+                    panic!("A handle was used after it was closed.");
+                }
+            }
+        }
+
+        #result
+    };
+    
+    quote! { #result }
+}
+
+// The number of callbacks of each type that can be minted at once. Each has a
+// function of its own, so that the library under test sees distinct pointers.
+const CALLBACK_SLOTS: usize = 16;
+
+// A function pointer type, such as 'type OnEvent = extern "C" fn(*mut c_void, c_int)', is
+// controlled through Mock<Name>, which mints callbacks of that type from mock methods.
+fn parse_callback(type_item: syn::ItemType, args: &MockArgs) -> proc_macro2::TokenStream {
+    let bare_fn = match *type_item.ty {
+        syn::Type::BareFn(ref bare_fn) => bare_fn.clone(),
+        ref other => return syn::Error::new_spanned(other, "#[mock] can only be applied to a type alias of a function pointer").to_compile_error(),
+    };
+    if let Some(ref lifetimes) = bare_fn.lifetimes {
+        return syn::Error::new_spanned(lifetimes, "Mocking callbacks with lifetimes is not supported").to_compile_error();
+    }
+    if let Some(ref variadic) = bare_fn.variadic {
+        return syn::Error::new_spanned(variadic, "Mocking variadic callbacks is not supported").to_compile_error();
+    }
+    if !type_item.generics.params.is_empty() {
+        return syn::Error::new_spanned(&type_item.generics, "Mocking generic callback types is not supported").to_compile_error();
+    }
+
+    // Libraries often call back from threads of their own, so callbacks are shared by every
+    // thread unless told otherwise.
+    let storage = args.storage.unwrap_or(Storage::Global);
+    let ref alias = type_item.ident;
+    let ref unsafety = bare_fn.unsafety;
+    let ref abi = bare_fn.abi;
+    let controller = match args.name {
+        Some(ref name) => quote! { #name },
+        None => concat!("Mock", alias),
+    };
+    let controller_vis = match args.vis {
+        Some(ref vis) => quote! { #vis },
+        None => quote_field!(&type_item.vis),
+    };
+    let callback_name = concat!(controller, "Callback");
+    let name = concat!("Method_", alias);
+    let slot_name = concat!("CallbackSlot_", alias);
+    let slots_name = concat!("CallbackSlots_", alias);
+    let static_name = concat!("Static", controller);
+    let dispatch_name = concat!("__mock_dispatch_", alias);
+    let router_name = concat!("__mock_router_", alias);
+
+    // The arguments are named, so that they can be recorded and returned.
+    let inputs = bare_fn.inputs.iter().map(|input| &input.ty);
+    let sig: syn::Signature = parse_quote! { fn callback(#(_: #inputs),*) };
+    let fn_args = parse_args(sig.inputs.iter());
+    let ref args_with_types = fn_args.args_with_types;
+    let ref args_with_no_self_no_types = fn_args.args_with_no_self_no_types;
+    let arg_names: Vec<_> = fn_args.typed_args.iter().map(|&(ref tok, _)| tok).collect();
+    let arg_types: Vec<_> = fn_args.typed_args.iter().map(|&(_, ref ty)| ty).collect();
+    let (no_return, return_type) = parse_return_type(&bare_fn.output);
+    let arg_returns = generate_arg_returns(&fn_args, &bare_fn.output, &ReturnKind::Owned, &FutureKind::Ready);
+    let (return_statement,
+         retval_statement,
+         some_arg) = make_return_tokens(no_return, &return_type, &ReturnKind::Owned, &FutureKind::Ready);
+    let fn_body = generate_fn_body(&proc_macro2::TokenStream::new(),
+                                   &quote! { Some(&*method) },
+                                   &FutureKind::Ready,
+                                   (&some_arg, &retval_statement),
+                                   &arg_returns,
+                                   &quote! { panic!("Called a mocked callback without a result set."); },
+                                   &quote! { unreachable!(); });
+    let fallback_value = fallback_value(&bare_fn.output);
+    let mock_method_body = generate_mock_method_body(&pub_token!(), &name, &storage.send_bound());
+    let mock_method_ctor = generate_mock_method_ctor(&name, &FutureKind::Ready);
+
+    // The user data of a callback is its first void pointer. Callbacks that take one can also
+    // be called through the router, which finds the callback that was minted with it.
+    let user_data = fn_args.typed_args.iter().find(|&&(_, ref ty)| match *ty {
+        syn::Type::Ptr(ref ptr) => match *ptr.elem {
+            syn::Type::Path(ref path) => path.path.segments.last().is_some_and(|segment| segment.ident == "c_void"),
+            _ => false,
+        },
+        _ => false,
+    });
+    let mut user_data_check = proc_macro2::TokenStream::new();
+    let mut router = proc_macro2::TokenStream::new();
+    let mut user_data_accessor = proc_macro2::TokenStream::new();
+    if let Some(&(ref tok, ref ty)) = user_data {
+        user_data_check = quote! {
+            if #tok as usize != slot.user_data {
+                panic!("Callback {} was called with the user data {:?}, but was minted with {:#x}", index, #tok, slot.user_data);
+            }
+        };
+        user_data_accessor = quote! {
+            // The user data to hand to the library along with the function.
+            pub fn user_data(&self) -> #ty {
+                self.user_data as #ty
+            }
+        };
+        router = quote! {
+            #[allow(dead_code)]
+            #[allow(non_snake_case)]
+            #[allow(unused_variables)]
+            #unsafety #abi fn #router_name(#args_with_types) #return_statement {
+                let index = {
+                    let value = #static_name();
+                    let singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                    singleton.slots.iter().position(|slot| slot.as_ref().is_some_and(|slot| slot.user_data == #tok as usize))
+                };
+                match index {
+                    Some(index) => #dispatch_name(index, #args_with_no_self_no_types),
+                    None => {
+                        eprintln!("A callback of {} was routed with the user data {:?}, which no callback was minted with", stringify!(#controller), #tok);
+                        #fallback_value
+                    },
+                }
+            }
+
+            #[allow(dead_code)]
+            impl #controller {
+                // A single function for every callback, which calls the one minted with its user data.
+                pub fn router() -> #alias {
+                    #router_name
+                }
+            }
+        };
+    }
+
+    let trampoline_names: Vec<_> = (0..CALLBACK_SLOTS).map(|index| concat!("__mock_callback_", alias, format!("_{}", index))).collect();
+    let trampolines = trampoline_names.iter().enumerate().map(|(index, trampoline)| quote! {
+        #[allow(dead_code)]
+        #[allow(non_snake_case)]
+        #[allow(unused_variables)]
+        #unsafety #abi fn #trampoline(#args_with_types) #return_statement {
+            #dispatch_name(#index, #args_with_no_self_no_types)
+        }
+    });
+    // Calls are recorded by cloning their arguments, which is checked at each argument type.
+    let clone_checks = arg_types.iter().map(|ty| quote_spanned! { syn::spanned::Spanned::span(ty)=>
+        const _: fn() = || {
+            fn recorded_arguments_must_be_clone<T: ::std::clone::Clone>() {}
+            recorded_arguments_must_be_clone::<#ty>();
+        };
+    });
+    let dropped_message = match storage {
+        Storage::ThreadLocal => "Callback {} was called after it was dropped, or from a thread other than the one that minted it",
+        Storage::Global => "Callback {} was called after it was dropped",
+    };
+    // The lock is released before the method runs, as its closures may call other callbacks.
+    let dispatch_body = generate_recorded_call(&quote! {
+        let method = {
+            let value = #static_name();
+            let mut singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+            let slot = match singleton.slots[index].as_mut() {
+                Some(slot) => slot,
+                None => panic!(#dropped_message, index),
+            };
+            #user_data_check
+            slot.calls.push((#(::std::clone::Clone::clone(&#arg_names),)*));
+            ::std::sync::Arc::clone(&slot.method)
+        };
+        #fn_body
+    }, &quote! {
+        let value = #static_name();
+        let mut singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+        match singleton.slots[index].as_mut() {
+            Some(slot) => {
+                slot.failures.push(failure);
+                match slot.method.fallback {
+                    Some(ref fallback) => fallback(),
+                    None => #fallback_value,
+                }
+            },
+            None => #fallback_value,
+        }
+    });
+
+    let slots_init = quote! {
+        #slots_name { slots: (0..#CALLBACK_SLOTS).map(|_| None).collect() }
+    };
+    let external_static = make_mut_static(&static_name, &slots_name, &slots_init, storage);
+    let mut lock = proc_macro2::TokenStream::new();
+    if storage == Storage::Global {
+        let lock_name = concat!("__lock_", static_name);
+        let returned = match bare_fn.output {
+            syn::ReturnType::Type(_, ref ty) => Some(&**ty),
+            syn::ReturnType::Default => None,
+        };
+        let send_check = assert_send_unless_pointer(arg_types.iter().cloned().chain(returned));
+        lock = quote! {
+            // Sound once the stored types are checked, see 'assert_send_unless_pointer'.
+            #send_check
+            unsafe impl Send for #slots_name {}
+
+            #[allow(dead_code)]
+            impl #controller {
+                /// Held by a test for as long as it mints callbacks, which start out all free.
+                /// Tests that don't hold it share the callbacks with every other test running
+                /// at the same time.
+                pub fn lock() -> ::std::sync::MutexGuard<'static, ()> {
+                    #lock_name()
+                }
+            }
+        };
+    }
+
+    quote! {
+        #type_item
+
+        #(#clone_checks)*
+
+        #[allow(dead_code)]
+        #controller_vis struct #controller;
+
+        #[allow(dead_code)]
+        #[allow(non_camel_case_types)]
+        struct #slot_name {
+            method: ::std::sync::Arc<#name<#return_type>>,
+            calls: Vec<(#(#arg_types,)*)>,
+            failures: Vec<String>,
+            user_data: usize,
+        }
+
+        #[allow(non_camel_case_types)]
+        struct #slots_name {
+            slots: Vec<Option<#slot_name>>,
+        }
+
+        #[allow(dead_code)]
+        #external_static
+
+        #mock_method_body
+
+        #lock
+
+        #[allow(dead_code)]
+        #[allow(non_snake_case)]
+        fn #dispatch_name(index: usize, #args_with_types) #return_statement {
+            #dispatch_body
+        }
+
+        #(#trampolines)*
+
+        #router
+
+        #[allow(dead_code)]
+        impl #controller {
+            pub fn method() -> #name<#return_type> {
+                #mock_method_ctor
+            }
+
+            // Binds 'method' to a callback of its own, until the callback is dropped.
+            pub fn mint(method: #name<#return_type>) -> #callback_name {
+                // Each callback gets a distinct allocation as its user data, which is never freed.
+                let user_data = ::std::boxed::Box::into_raw(::std::boxed::Box::new(0u64)) as usize;
+                let value = #static_name();
+                let mut singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                let index = match singleton.slots.iter().position(|slot| slot.is_none()) {
+                    Some(index) => index,
+                    None => panic!("All {} callbacks of {} are in use. Drop some of them first.", #CALLBACK_SLOTS, stringify!(#controller)),
+                };
+                singleton.slots[index] = Some(#slot_name {
+                    method: ::std::sync::Arc::new(method),
+                    calls: Vec::new(),
+                    failures: Vec::new(),
+                    user_data: user_data,
+                });
+
+                #callback_name {
+                    index: index,
+                    user_data: user_data,
+                }
+            }
+        }
+
+        // A callback minted from a mock method. Dropping it frees the callback, and verifies
+        // its method, along with the failures recorded while it was called.
+        #[allow(dead_code)]
+        #[must_use = "a callback is freed as soon as it is dropped"]
+        #controller_vis struct #callback_name {
+            index: usize,
+            user_data: usize,
+        }
+
+        #[allow(dead_code)]
+        impl #callback_name {
+            const FUNCTIONS: [#alias; #CALLBACK_SLOTS] = [#(#trampoline_names),*];
+
+            // The function to hand to the library under test.
+            pub fn function(&self) -> #alias {
+                Self::FUNCTIONS[self.index]
+            }
+
+            #user_data_accessor
+
+            // The arguments of every call so far.
+            pub fn calls(&self) -> Vec<(#(#arg_types,)*)> {
+                let value = #static_name();
+                let singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                match singleton.slots[self.index] {
+                    Some(ref slot) => slot.calls.clone(),
+                    None => Vec::new(),
+                }
+            }
+
+            pub fn call_count(&self) -> usize {
+                self.calls().len()
+            }
+
+            // Panics with the failures recorded while the callback was called, and forgets them.
+            pub fn verify(&self) {
+                let failures = {
+                    let value = #static_name();
+                    let mut singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                    match singleton.slots[self.index] {
+                        Some(ref mut slot) => ::std::mem::take(&mut slot.failures),
+                        None => Vec::new(),
+                    }
+                };
+                Self::raise_failures(&failures);
+            }
+
+            fn raise_failures(failures: &[String]) {
+                if failures.is_empty() {
+                    return;
+                }
+
+                let message = failures.join("\n");
+                if ::std::thread::panicking() {
+                    eprintln!("A callback of {} recorded failures:\n{}", stringify!(#controller), message);
+                } else {
+                    panic!("A callback of {} recorded failures:\n{}", stringify!(#controller), message);
+                }
+            }
+        }
+
+        impl ::std::ops::Drop for #callback_name {
+            fn drop(&mut self) {
+                // The lock is released before the method of the callback is verified.
+                let slot = {
+                    let value = #static_name();
+                    let mut singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                    singleton.slots[self.index].take()
+                };
+                if let Some(slot) = slot {
+                    Self::raise_failures(&slot.failures);
+                }
+            }
+        }
+    }
+}
+
+// The C functions that a preloaded library calls while it loads its scenario and logs calls,
+// along with the allocator and the locks of std. Preloading them would call back into the
+// library, which deadlocks on its own state.
+const PRELOAD_RESERVED: &[&str] = &[
+    "getenv", "malloc", "calloc", "realloc", "free", "posix_memalign", "aligned_alloc", "memalign",
+    "open", "open64", "openat", "openat64", "read", "write", "writev", "close", "fstat", "fstat64",
+    "stat", "stat64", "statx", "lseek", "lseek64", "fcntl", "syscall", "memcpy", "memmove", "memset",
+    "memcmp", "bcmp", "strlen", "pthread_mutex_lock", "pthread_mutex_unlock", "__errno_location", "abort",
+];
+
+// Defines the functions of an extern block for a library that is loaded with LD_PRELOAD
+// in front of the real one, so that a separately built binary can be tested against it.
+// Results come from the scenario file named by <PREFIX>_SCENARIO, and from the lines of
+// <PREFIX>_RESULTS, separated by ';'. Every call is appended to the file named by
+// <PREFIX>_CALL_LOG.
+fn parse_preload_functions(func_block: &syn::ItemForeignMod, args: &MockArgs, prefix: &syn::LitStr) -> proc_macro2::TokenStream {
+    let extern_name = extern_mock_name(func_block, args);
+    let state_name = concat!("PreloadState", extern_name);
+    let static_name = concat!("Static", state_name);
+    let scenario_var = format!("{}_SCENARIO", prefix.value());
+    let results_var = format!("{}_RESULTS", prefix.value());
+    let log_var = format!("{}_CALL_LOG", prefix.value());
+    let abi = match func_block.abi.name {
+        Some(ref name) => quote! { extern #name },
+        None => quote! { extern },
+    };
+
+    let mut functions = proc_macro2::TokenStream::new();
+    for item in &func_block.items {
+        match *item {
+            syn::ForeignItem::Fn(ref fn_item) => {
+                let ref decl = fn_item.sig;
+                if decl.generics.type_params().count() > 0 || decl.generics.lifetimes().count() > 0 {
+                    return syn::Error::new_spanned(&decl.generics, "Preloading extern functions with generics/lifetimes is not supported").to_compile_error();
+                }
+
+                if let Some(ref variadic) = decl.variadic {
+                    return syn::Error::new_spanned(variadic, "Preloading variadic extern functions is not supported").to_compile_error();
+                }
+                if PRELOAD_RESERVED.iter().any(|name| decl.ident == name) {
+                    return syn::Error::new_spanned(&decl.ident, format!("The preloaded library calls {} itself while it reads the scenario or logs calls, so it can't be preloaded", decl.ident))
+                        .to_compile_error();
+                }
+
+                let fn_args = parse_args(decl.inputs.iter());
+                let ref args_with_types = fn_args.args_with_types;
+                let arg_names: Vec<_> = fn_args.typed_args.iter().map(|&(ref tok, _)| tok).collect();
+                let (no_return, return_type) = parse_return_type(&decl.output);
+                let (return_statement, _, _) = make_return_tokens(no_return, &return_type, &ReturnKind::Owned, &FutureKind::Ready);
+                let fallback_value = fallback_value(&decl.output);
+                let fn_name = decl.ident.to_string();
+                // Pointers are given as addresses, and every other result is parsed with FromStr.
+                let parse_result = match decl.output {
+                    syn::ReturnType::Default => quote! { () },
+                    syn::ReturnType::Type(_, ref ty) => match **ty {
+                        syn::Type::Ptr(_) => quote! {
+                            match #state_name::parse_address(text) {
+                                Some(address) => address as #return_type,
+                                None => panic!("Can't parse the result '{}' of {} as an address", text, #fn_name),
+                            }
+                        },
+                        _ => quote! {
+                            match text.parse::<#return_type>() {
+                                Ok(retval) => retval,
+                                Err(_) => panic!("Can't parse the result '{}' of {}", text, #fn_name),
+                            }
+                        },
+                    },
+                };
+                let log_result = match no_return {
+                    true => quote! { String::new() },
+                    false => quote! { format!(" -> {:?}", retval) },
+                };
+                let body = generate_recorded_call(&quote! {
+                    let value = #static_name();
+                    let mut state = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                    let arguments: Vec<String> = vec![#(format!("{:?}", #arg_names)),*];
+                    let retval: #return_type = match state.next_result(#fn_name) {
+                        Some(ref text) => #parse_result,
+                        None => #fallback_value,
+                    };
+                    state.log(&format!("{}({}){}", #fn_name, arguments.join(", "), #log_result));
+                    retval
+                }, &quote! {
+                    let value = #static_name();
+                    let mut state = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                    state.log(&format!("{} failed: {}", #fn_name, failure));
+                    #fallback_value
+                });
+
+                let ref ident = decl.ident;
+                functions.extend(quote! {
+                    #[allow(unused_variables)]
+                    #[allow(dead_code)]
                     #[no_mangle]
-                    #pubtok unsafe #abi fn #base_name (#args_with_types) #return_statement {
-                        let value = #static_name();
-                        let singleton = value.inner.lock().unwrap();
-                        if let Some(ref method) = singleton.#name_lc {
-                            match method.call() {
-                                Some(#some_arg) => {
-                                    #retval_statement
-                                },
-                                None => {
-                                    panic!("Called a static mock function without a value set.");
-                                }
-                            }
-                        } else {
-                            panic!();
-                        }
+                    pub unsafe #abi fn #ident(#args_with_types) #return_statement {
+                        #body
+                    }
+                });
+            },
+            syn::ForeignItem::Type(ref type_item) => {
+                let ref attrs = type_item.attrs;
+                let ref vis = type_item.vis;
+                let ref ident = type_item.ident;
+                functions.extend(quote! {
+                    #(#attrs)*
+                    #[allow(non_camel_case_types)]
+                    #[allow(dead_code)]
+                    #[repr(C)]
+                    #vis struct #ident {
+                        _private: [u8; 0],
+                    }
+                });
+            },
+            ref other => return syn::Error::new_spanned(other, "Only functions and types can be preloaded").to_compile_error(),
+        }
+    }
+
+    let external_static = make_mut_static(&static_name, &state_name, &quote! {
+        #state_name::load()
+    }, Storage::Global);
+
+    quote! {
+        #[allow(dead_code)]
+        #[allow(non_camel_case_types)]
+        struct #state_name {
+            // The results of each function, in the order that they are returned. The last
+            // result of a function is returned by every call after it.
+            results: ::std::collections::HashMap<String, ::std::collections::VecDeque<String>>,
+            log: Option<::std::fs::File>,
+        }
+
+        #[allow(dead_code)]
+        impl #state_name {
+            fn load() -> #state_name {
+                let mut scenario = String::new();
+                if let Ok(path) = ::std::env::var(#scenario_var) {
+                    match ::std::fs::read_to_string(&path) {
+                        Ok(text) => scenario.push_str(&text),
+                        Err(err) => eprintln!("Can't read the scenario file {}: {}", path, err),
                     }
                 }
-            },
-            syn::ForeignItem::Static(_) => {
-                panic!("Mocking statics not yet supported.");
-            },
-            syn::ForeignItem::Type(_) => {
-                panic!("Mocking foreign types not yet supported.");
-            },
-            syn::ForeignItem::Macro(_) => {
-                panic!("Mocking macros not supported.");
-            },
-            syn::ForeignItem::Verbatim(_) => {
-                panic!("Mocking not supported for unparsed items.");
-            },
-            _ => {
-                panic!("Mocking not support for this variant.");
+                if let Ok(results) = ::std::env::var(#results_var) {
+                    scenario.push('\n');
+                    scenario.push_str(&results.replace(';', "\n"));
+                }
+
+                let mut results = ::std::collections::HashMap::new();
+                for line in scenario.lines().map(str::trim) {
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+
+                    match line.split_once('=') {
+                        Some((name, result)) => results.entry(name.trim().to_string())
+                            .or_insert_with(::std::collections::VecDeque::new)
+                            .push_back(result.trim().to_string()),
+                        None => eprintln!("Ignoring the scenario line '{}', which is not 'function = result'", line),
+                    }
+                }
+
+                let log = ::std::env::var(#log_var).ok().and_then(|path| {
+                    ::std::fs::OpenOptions::new().create(true).append(true).open(&path)
+                        .map_err(|err| eprintln!("Can't open the call log {}: {}", path, err))
+                        .ok()
+                });
+
+                #state_name {
+                    results: results,
+                    log: log,
+                }
+            }
+
+            fn next_result(&mut self, name: &str) -> Option<String> {
+                let results = self.results.get_mut(name)?;
+                if results.len() > 1 {
+                    results.pop_front()
+                } else {
+                    results.front().cloned()
+                }
+            }
+
+            fn log(&mut self, line: &str) {
+                use ::std::io::Write;
+                if let Some(ref mut log) = self.log {
+                    // Each line is a single write, so that the lines of several processes
+                    // appending to the same log don't interleave.
+                    let _ = log.write_all(format!("{}\n", line).as_bytes());
+                }
+            }
+
+            fn parse_address(text: &str) -> Option<usize> {
+                match text.strip_prefix("0x") {
+                    Some(hex) => usize::from_str_radix(hex, 16).ok(),
+                    None => text.parse().ok(),
+                }
+            }
+        }
+
+        #[allow(dead_code)]
+        #external_static
+
+        #functions
+    }
+}
+
+// Blocks that link to a library are named after it, so that blocks with the same ABI
+// don't collide.
+fn extern_mock_name(func_block: &syn::ItemForeignMod, args: &MockArgs) -> proc_macro2::TokenStream {
+    let type_name = match func_block.abi.name {
+        Some(ref name) => name.value().replace("extern", "").replace("\"", ""),
+        None => String::from("Rust"),
+    };
+
+    match (&args.name, link_name(&func_block.attrs)) {
+        (&Some(ref name), _) => quote! { #name },
+        (&None, Some(link_name)) => concat!(camel_case(&link_name), "Mocks"),
+        (&None, None) => concat!("Extern", type_name, "Mocks"),
+    }
+}
+
+// Whether all zero bytes are a valid value of 'ty', as far as can be told from its name.
+// Any type missed here is still caught when the zeroed static is evaluated.
+fn can_be_zeroed(ty: &syn::Type) -> bool {
+    const NON_ZERO: &[&str] = &["NonNull", "Box", "Vec", "String", "Rc", "Arc"];
+    match *ty {
+        syn::Type::Reference(_) | syn::Type::BareFn(_) | syn::Type::Never(_) => false,
+        syn::Type::Array(ref array) => can_be_zeroed(&array.elem),
+        syn::Type::Tuple(ref tuple) => tuple.elems.iter().all(can_be_zeroed),
+        syn::Type::Paren(ref paren) => can_be_zeroed(&paren.elem),
+        syn::Type::Group(ref group) => can_be_zeroed(&group.elem),
+        syn::Type::Path(ref path) => {
+            let ref ident = path.path.segments.last().unwrap().ident;
+            !NON_ZERO.iter().any(|name| ident == name) && !ident.to_string().starts_with("NonZero")
+        },
+        _ => true,
+    }
+}
+
+// The library named by the #[link(name = "..")] attribute of an extern block.
+fn link_name(attrs: &[syn::Attribute]) -> Option<String> {
+    attrs.iter()
+        .filter(|attr| attr.path.is_ident("link"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(syn::Meta::List(list)) => Some(list.nested),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|nested| match nested {
+            syn::NestedMeta::Meta(syn::Meta::NameValue(ref pair)) if pair.path.is_ident("name") => match pair.lit {
+                syn::Lit::Str(ref name) => Some(name.value()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .next()
+}
+
+// Whether a foreign function is marked with #[mock(closes_handle)].
+fn closes_handle(attrs: &[syn::Attribute]) -> Result<bool, syn::Error> {
+    let mut closes_handle = false;
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("mock")) {
+        match attr.parse_meta()? {
+            syn::Meta::List(ref list) if list.nested.len() == 1 => match list.nested[0] {
+                syn::NestedMeta::Meta(syn::Meta::Path(ref path)) if path.is_ident("closes_handle") => closes_handle = true,
+                ref other => return Err(syn::Error::new_spanned(other, "Unknown mock argument. Expected closes_handle")),
+            },
+            ref other => return Err(syn::Error::new_spanned(other, "Expected #[mock(closes_handle)]")),
+        }
+    }
+
+    Ok(closes_handle)
+}
+
+// Whether a function of a mocked module is marked with #[mock(unmocked)].
+fn unmocked(attrs: &[syn::Attribute]) -> Result<bool, syn::Error> {
+    let mut unmocked = false;
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("mock")) {
+        match attr.parse_meta()? {
+            syn::Meta::List(ref list) if list.nested.len() == 1 => match list.nested[0] {
+                syn::NestedMeta::Meta(syn::Meta::Path(ref path)) if path.is_ident("unmocked") => unmocked = true,
+                ref other => return Err(syn::Error::new_spanned(other, "Unknown mock argument. Expected unmocked")),
+            },
+            ref other => return Err(syn::Error::new_spanned(other, "Expected #[mock(unmocked)]")),
+        }
+    }
+
+    Ok(unmocked)
+}
+
+// The value returned by a failed call that was recorded, when no fallback is set.
+// C types without a Default are pointers, which fall back to null.
+fn fallback_value(output: &syn::ReturnType) -> proc_macro2::TokenStream {
+    match *output {
+        syn::ReturnType::Default => quote! { () },
+        syn::ReturnType::Type(_, ref ty) => match **ty {
+            syn::Type::Ptr(ref ptr) if ptr.mutability.is_some() => quote! { ::std::ptr::null_mut() },
+            syn::Type::Ptr(_) => quote! { ::std::ptr::null() },
+            _ => quote! { ::std::default::Default::default() },
+        },
+    }
+}
+
+// Runs 'body' for a mock that is called from C, which a panic can't unwind through.
+// The message of a panic is given to 'record' as 'failure', and 'record' evaluates
+// to the value returned in place of the result.
+fn generate_recorded_call(body: &proc_macro2::TokenStream, record: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    quote! {
+        let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+            #body
+        }));
+        match result {
+            Ok(retval) => retval,
+            Err(payload) => {
+                let failure = match payload.downcast_ref::<&str>() {
+                    Some(message) => message.to_string(),
+                    None => match payload.downcast_ref::<String>() {
+                        Some(message) => message.clone(),
+                        None => String::from("The mock panicked"),
+                    },
+                };
+                #record
+            }
+        }
+    }
+}
+
+// The guard returned by the scopes of a controller. Dropping it puts back the mocks
+// that the scope replaced, and then drops the mocks of the scope, which verifies them.
+fn generate_scope_guard(vis: &proc_macro2::TokenStream, guard_name: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    quote! {
+        #[allow(dead_code)]
+        #[must_use = "the mocks of a scope are restored as soon as its guard is dropped"]
+        #vis struct #guard_name {
+            restore: Option<Box<dyn FnOnce()>>,
+        }
+
+        impl ::std::ops::Drop for #guard_name {
+            fn drop(&mut self) {
+                if let Some(restore) = self.restore.take() {
+                    restore();
+                }
             }
         }
     }
+}
 
-    let external_static = make_mut_static(&quote! { #static_name }, &quote! { #extern_name }, &quote!{
-        #extern_name { #extern_mocks_ctor_args }
-    });
-    result = quote!{
+// 'scoped_name' sets the mock method in 'field' of the storage 'static_name' until its guard
+// is dropped. 'verify' runs once the method of the scope has been taken back, as 'scoped'.
+fn generate_scoped_setter(scoped_name: &proc_macro2::TokenStream,
+                          guard_name: &proc_macro2::TokenStream,
+                          static_name: &proc_macro2::TokenStream,
+                          field: &proc_macro2::TokenStream,
+                          method_type: &proc_macro2::TokenStream,
+                          verify: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    quote! {
         #[allow(dead_code)]
-        #[allow(unused_variables)]
-        pub struct #extern_name {
-            #extern_mocks_def
+        pub fn #scoped_name(x: #method_type) -> #guard_name {
+            let previous = {
+                let value = #static_name();
+                let mut singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                ::std::mem::replace(&mut singleton.#field, Some(x))
+            };
+
+            #guard_name {
+                restore: Some(Box::new(move || {
+                    // The lock is released before the mock of the scope is verified.
+                    let scoped = {
+                        let value = #static_name();
+                        let mut singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                        ::std::mem::replace(&mut singleton.#field, previous)
+                    };
+                    #verify
+                    drop(scoped);
+                })),
+            }
         }
-        
+    }
+}
+
+// 'scope_name' replaces every mock of the storage 'static_name' with the value made by
+// 'init_body', until its guard is dropped. 'verify' runs on the mocks of the scope, as 'scoped'.
+fn generate_scope(scope_name: &proc_macro2::TokenStream,
+                  guard_name: &proc_macro2::TokenStream,
+                  static_name: &proc_macro2::TokenStream,
+                  init_body: &proc_macro2::TokenStream,
+                  verify: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    quote! {
         #[allow(dead_code)]
-        #[allow(unused_variables)]
-        #external_static
+        pub fn #scope_name() -> #guard_name {
+            let previous = {
+                let value = #static_name();
+                let mut singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                ::std::mem::replace(&mut *singleton, { #init_body })
+            };
 
-        #result
-    };
-    
-    quote! { #result }
+            #guard_name {
+                restore: Some(Box::new(move || {
+                    let scoped = {
+                        let value = #static_name();
+                        let mut singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                        ::std::mem::replace(&mut *singleton, previous)
+                    };
+                    #verify
+                    drop(scoped);
+                })),
+            }
+        }
+    }
+}
+
+// Mocks shared by every thread hold the arguments and results of foreign functions. Raw
+// pointers among them are only addresses until they are dereferenced, which takes unsafe
+// code of its own, so they may be sent to other threads. Every other type is checked to be
+// Send, which the mock is then declared to be.
+fn assert_send_unless_pointer<'a, I: Iterator<Item = &'a syn::Type>>(types: I) -> proc_macro2::TokenStream {
+    let checked = types.filter(|ty| !matches!(**ty, syn::Type::Ptr(_)));
+    quote! {
+        const _: fn() = || {
+            fn assert_send<T: Send>() {}
+            #(assert_send::<#checked>();)*
+        };
+    }
 }
 
-// https://stackoverflow.com/questions/27791532/how-do-i-create-a-global-mutable-singleton
-fn make_mut_static(ident: &proc_macro2::TokenStream, ty: &proc_macro2::TokenStream, init_body: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+// The function 'ident' hands out the storage of a mock, which holds a 'ty' made by
+// 'init_body'. Global storage also gets '__reset_<ident>', to put back a fresh value,
+// and '__lock_<ident>', which serializes the tests that use it.
+fn make_mut_static(ident: &proc_macro2::TokenStream, ty: &proc_macro2::TokenStream, init_body: &proc_macro2::TokenStream, storage: Storage) -> proc_macro2::TokenStream {
     let reader_name = concat!("__SingletonReader_", &ident);
     let singleton_name = concat!("__SINGLETON_", &ident);
-    quote! {
-        #[allow(non_camel_case_types)]
-        #[derive(Clone)]
-        struct #reader_name {
-            // Since we will be used in many threads, we need to protect
-            // concurrent access
-            inner: ::std::sync::Arc<::std::sync::Mutex<#ty>>
-        }
+    match storage {
+        Storage::ThreadLocal => quote! {
+            #[allow(non_camel_case_types)]
+            #[derive(Clone)]
+            struct #reader_name {
+                inner: ::std::rc::Rc<::std::sync::Mutex<#ty>>
+            }
 
-        #[allow(non_snake_case)]
-        fn #ident() -> #reader_name {
-            thread_local! {
-                #[allow(non_upper_case_globals)]
-                #[allow(non_snake_case)]
-                static #singleton_name: ::std::cell::RefCell<*const #reader_name> = ::std::cell::RefCell::new(0 as *const #reader_name);
-                static ONCE: ::std::sync::Once = ::std::sync::Once::new();
+            #[allow(non_snake_case)]
+            fn #ident() -> #reader_name {
+                thread_local! {
+                    #[allow(non_upper_case_globals)]
+                    #[allow(non_snake_case)]
+                    static #singleton_name: ::std::rc::Rc<::std::sync::Mutex<#ty>> = ::std::rc::Rc::new(::std::sync::Mutex::new({
+                        #init_body
+                    }));
+                }
+
+                #reader_name {
+                    inner: #singleton_name.with(::std::rc::Rc::clone),
+                }
             }
+        },
+        Storage::Global => {
+            let reset_name = concat!("__reset_", &ident);
+            let lock_name = concat!("__lock_", &ident);
+            let tests_name = concat!("__TESTS_", &ident);
+            quote! {
+                #[allow(non_camel_case_types)]
+                #[derive(Clone, Copy)]
+                struct #reader_name {
+                    inner: &'static ::std::sync::Mutex<#ty>
+                }
 
+                #[allow(non_snake_case)]
+                fn #ident() -> #reader_name {
+                    #[allow(non_upper_case_globals)]
+                    #[allow(non_snake_case)]
+                    static #singleton_name: ::std::sync::OnceLock<::std::sync::Mutex<#ty>> = ::std::sync::OnceLock::new();
 
-            unsafe {
-                ONCE.with(|once| {
-                    // This is horrible, but just TRY and stop me!
-                    let x: &'static ::std::sync::Once = ::std::mem::transmute(once);
-                    x.call_once(|| {
-                        // Make it
-                        let init_fn = || {
+                    #reader_name {
+                        inner: #singleton_name.get_or_init(|| ::std::sync::Mutex::new({
                             #init_body
-                        };
-                        let singleton = #reader_name {
-                            inner: ::std::sync::Arc::new(::std::sync::Mutex::new(init_fn()))
-                        };
-                        
-                        // Put it in the heap so it can outlive this call
-                        #singleton_name.with(|f| {
-                            *f.borrow_mut() = ::std::mem::transmute(::std::boxed::Box::new(singleton));
-                        });
-                    });
-                });
+                        })),
+                    }
+                }
+
+                #[allow(dead_code)]
+                #[allow(non_snake_case)]
+                fn #reset_name() {
+                    // A test that panicked while holding the storage leaves nothing behind
+                    // that outlives this reset.
+                    let value = #ident();
+                    let mut singleton = value.inner.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                    *singleton = { #init_body };
+                }
+
+                #[allow(dead_code)]
+                #[allow(non_snake_case)]
+                fn #lock_name() -> ::std::sync::MutexGuard<'static, ()> {
+                    #[allow(non_upper_case_globals)]
+                    #[allow(non_snake_case)]
+                    static #tests_name: ::std::sync::Mutex<()> = ::std::sync::Mutex::new(());
 
-                // Now we give out a copy of the data that is safe to use concurrently.
-                #singleton_name.with(|f| {
-                    (**f.borrow()).clone()
-                })
+                    // The previous holder may have failed its test, which is no reason to
+                    // fail this one.
+                    let guard = #tests_name.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                    #reset_name();
+                    guard
+                }
             }
+        },
+    }
+}
+
+fn generate_mock_method_ctor(mock_method_name: &proc_macro2::TokenStream, future_kind: &FutureKind) -> proc_macro2::TokenStream {
+    let returns_future = !matches!(*future_kind, FutureKind::Ready);
+    quote! {
+        #mock_method_name {
+            returns_future: #returns_future,
+            call_num: ::std::sync::Mutex::new(1),
+            current_num: ::std::sync::Mutex::new(1),
+            retval: ::std::sync::Mutex::new(::std::collections::HashMap::new()),
+            lambda: ::std::sync::Mutex::new(None),
+            future_lambda: ::std::sync::Mutex::new(None),
+            should_never_be_called: false,
+            max_calls: None,
+            min_calls: None,
+            returned_arg: None,
+            retained: ::std::sync::Mutex::new(Vec::new()),
+            fallback: None,
         }
     }
 }
 
-fn generate_mock_method_body(pubtok: &proc_macro2::TokenStream, mock_method_name: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+// The most results that a method returning a borrow of its mock keeps alive.
+const RETAINED_RESULTS: usize = 65536;
+
+// 'send_bound' is added to the bounds of the closures held by the method, for methods
+// whose expectations are shared between threads.
+fn generate_mock_method_body(pubtok: &proc_macro2::TokenStream, mock_method_name: &proc_macro2::TokenStream, send_bound: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    let trigger_name = concat!(mock_method_name, "Trigger");
+    let deferred_name = concat!(mock_method_name, "Deferred");
+    let state_name = concat!(mock_method_name, "DeferredState");
+    let sequence_name = concat!(mock_method_name, "Sequence");
+    let yield_name = concat!(mock_method_name, "Yield");
     quote!{ 
         #[allow(dead_code)]
         #[allow(non_camel_case_types)]
         #pubtok struct #mock_method_name<__RESULT_NAME> {
+            // Only async fns, and fns returning a future, can be given a future to await.
+            pub returns_future: bool,
             pub call_num: ::std::sync::Mutex<usize>,
             pub current_num: ::std::sync::Mutex<usize>,
             pub retval: ::std::sync::Mutex<::std::collections::HashMap<usize, __RESULT_NAME>>,
-            pub lambda: ::std::sync::Mutex<Option<Box<FnMut() -> __RESULT_NAME>>>,
+            pub lambda: ::std::sync::Mutex<Option<Box<dyn FnMut() -> __RESULT_NAME #send_bound>>>,
+            pub future_lambda: ::std::sync::Mutex<Option<Box<dyn FnMut() -> ::std::pin::Pin<Box<dyn ::std::future::Future<Output = __RESULT_NAME> + Send>> #send_bound>>>,
             pub should_never_be_called: bool,
             pub max_calls: Option<usize>,
             pub min_calls: Option<usize>,
+            pub returned_arg: Option<usize>,
+            pub retained: ::std::sync::Mutex<Vec<Box<__RESULT_NAME>>>,
+            pub fallback: Option<Box<dyn Fn() -> __RESULT_NAME #send_bound>>,
         }
 
         #[allow(dead_code)]
@@ -816,7 +3658,7 @@ fn generate_mock_method_body(pubtok: &proc_macro2::TokenStream, mock_method_name
 
             pub fn nth_call(self, num: usize) -> Self {
                 {
-                    let mut value = self.call_num.lock().unwrap();
+                    let mut value = self.call_num.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
                     *value = num;
                 }
                 self
@@ -824,16 +3666,21 @@ fn generate_mock_method_body(pubtok: &proc_macro2::TokenStream, mock_method_name
 
             pub fn set_result(self, retval: __RESULT_NAME) -> Self {
                 {
-                    let lambda = self.lambda.lock().unwrap();
+                    let lambda = self.lambda.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
                     if lambda.is_some() {
                         panic!("Attempting to call set_result with after 'return_result_of' has been called. These two APIs are mutally exclusive, and should not be used together");
                     }
+
+                    let future_lambda = self.future_lambda.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                    if future_lambda.is_some() {
+                        panic!("Attempting to call set_result with after 'return_future_of' has been called. These two APIs are mutally exclusive, and should not be used together");
+                    }
                     
                 }
                 
                 {
-                    let call_num = self.call_num.lock().unwrap();
-                    let mut map = self.retval.lock().unwrap();
+                    let call_num = self.call_num.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                    let mut map = self.retval.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
                     map.insert(*call_num, retval);
                 }
                 self
@@ -881,12 +3728,12 @@ fn generate_mock_method_body(pubtok: &proc_macro2::TokenStream, mock_method_name
                 retval
             }
 
-            pub fn call(&self) -> Option<__RESULT_NAME> {
+            fn next_call_num(&self) -> usize {
                 if self.should_never_be_called {
                     panic!("Called a method that has been marked as 'never called'!");
                 }
 
-                let mut value = self.current_num.lock().unwrap();
+                let mut value = self.current_num.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
                 let current_num = *value;
                 *value += 1;
                 
@@ -894,26 +3741,271 @@ fn generate_mock_method_body(pubtok: &proc_macro2::TokenStream, mock_method_name
                     panic!("Method failed 'called at most', current number of calls is {}", current_num);
                 }
 
-                let mut lambda_result = self.lambda.lock().unwrap();
+                current_num
+            }
+
+            pub fn call(&self) -> Option<__RESULT_NAME> {
+                let current_num = self.next_call_num();
+                let mut lambda_result = self.lambda.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
                 match *lambda_result {
                     Some(ref mut lm) => {
                         Some(lm())
                     },
                     None => {
-                        let mut map = self.retval.lock().unwrap();
+                        let mut map = self.retval.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
                         map.remove(&current_num)
                     }
                 }                
             }
 
-            pub fn return_result_of<F: 'static>(self, lambda: F) -> Self
+            pub fn return_result_of<F: 'static #send_bound>(self, lambda: F) -> Self
                 where F: FnMut() -> __RESULT_NAME {
                 {
-                    let mut lambda_result = self.lambda.lock().unwrap();
+                    let mut lambda_result = self.lambda.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
                     *lambda_result = Some(Box::new(lambda));
                 }
                 self
             }
+
+            // Async fns, and fns returning a future, await the future made by 'lambda'
+            // on every call.
+            pub fn return_future_of<F: 'static #send_bound, Fut>(self, mut lambda: F) -> Self
+                where F: FnMut() -> Fut,
+                      Fut: ::std::future::Future<Output = __RESULT_NAME> + Send + 'static {
+                if !self.returns_future {
+                    panic!("Attempting to call return_future_of or deferred on a method that does not return a future. Use set_result or return_result_of instead");
+                }
+
+                {
+                    let lambda_result = self.lambda.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                    if lambda_result.is_some() {
+                        panic!("Attempting to call return_future_of with after 'return_result_of' has been called. These two APIs are mutally exclusive, and should not be used together");
+                    }
+                }
+
+                {
+                    let mut future_lambda = self.future_lambda.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                    *future_lambda = Some(Box::new(move || -> ::std::pin::Pin<Box<dyn ::std::future::Future<Output = __RESULT_NAME> + Send>> {
+                        Box::pin(lambda())
+                    }));
+                }
+                self
+            }
+
+            pub fn call_future(&self) -> Option<::std::pin::Pin<Box<dyn ::std::future::Future<Output = __RESULT_NAME> + Send>>> {
+                let mut future_lambda = self.future_lambda.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                match *future_lambda {
+                    Some(ref mut lambda) => {
+                        self.next_call_num();
+                        Some(lambda())
+                    },
+                    None => None,
+                }
+            }
+
+            // The future handed out by the next call stays pending until the returned
+            // trigger completes it. Only a single call can be deferred this way.
+            pub fn deferred(self) -> (Self, #trigger_name<__RESULT_NAME>)
+                where __RESULT_NAME: Send + 'static {
+                let state = ::std::sync::Arc::new(::std::sync::Mutex::new(#state_name {
+                    value: None,
+                    waker: None,
+                    called: false,
+                    polled: false,
+                    completed: false,
+                    cancelled: false,
+                }));
+                let trigger = #trigger_name { state: state.clone() };
+                let method = self.return_future_of(move || {
+                    {
+                        let mut value = state.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                        if value.called {
+                            panic!("A deferred method was called more than once. Only a single call can wait on a trigger");
+                        }
+                        value.called = true;
+                    }
+                    #deferred_name { state: state.clone() }
+                });
+                (method, trigger)
+            }
+
+            // Methods returning an iterator or a stream return one over 'items' for this call.
+            pub fn yields(self, items: Vec<<__RESULT_NAME as #sequence_name>::Item>) -> Self
+                where __RESULT_NAME: #sequence_name {
+                self.yields_steps(items.into_iter().map(Some).collect())
+            }
+
+            // A None step is a pending point, where a stream returns Poll::Pending once
+            // before moving on to the next step. Iterators skip pending points.
+            pub fn yields_steps(self, steps: Vec<Option<<__RESULT_NAME as #sequence_name>::Item>>) -> Self
+                where __RESULT_NAME: #sequence_name {
+                self.set_result(__RESULT_NAME::from_steps(steps))
+            }
+
+            // Yields each of 'items' as Ok, followed by 'err'.
+            pub fn yields_then_err<__ITEM_NAME, __ERROR_NAME>(self, items: Vec<__ITEM_NAME>, err: __ERROR_NAME) -> Self
+                where __RESULT_NAME: #sequence_name<Item = Result<__ITEM_NAME, __ERROR_NAME>> {
+                let mut steps: Vec<_> = items.into_iter().map(|item| Some(Ok(item))).collect();
+                steps.push(Some(Err(err)));
+                self.yields_steps(steps)
+            }
+
+            // The value returned by extern functions that record their failures, when a call fails.
+            pub fn fallback(mut self, value: __RESULT_NAME) -> Self
+                where __RESULT_NAME: Clone + 'static #send_bound {
+                self.fallback = Some(Box::new(move || value.clone()));
+                self
+            }
+
+            // Calls without a result set return the argument at 'index', not counting self.
+            pub fn returns_arg(mut self, index: usize) -> Self {
+                self.returned_arg = Some(index);
+                self
+            }
+
+            // Keeps 'value' alive for as long as this method, so that it can be handed
+            // out as a borrow of the mock. Earlier borrows may still be alive, so every
+            // value is kept until the method is dropped, up to a limit.
+            pub fn retain(&self, value: __RESULT_NAME) -> &__RESULT_NAME {
+                let boxed = Box::new(value);
+                let ptr: *const __RESULT_NAME = &*boxed;
+                let mut retained = self.retained.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                if retained.len() >= #RETAINED_RESULTS {
+                    panic!("A method returned more than {} results borrowed from its mock, which are kept until the method is dropped. Set a new method to free them", #RETAINED_RESULTS);
+                }
+                retained.push(boxed);
+                // Boxes are only dropped along with self, and moving a box does not
+                // move the value it points to.
+                unsafe { &*ptr }
+            }
+        }
+
+        // Implemented for the types that hold the result of a fn returning an iterator or a stream.
+        #[allow(non_camel_case_types)]
+        #pubtok trait #sequence_name {
+            type Item;
+
+            fn from_steps(steps: Vec<Option<Self::Item>>) -> Self;
+        }
+
+        #[allow(dead_code)]
+        #[allow(non_camel_case_types)]
+        #pubtok struct #yield_name<__ITEM_NAME> {
+            steps: ::std::collections::VecDeque<Option<__ITEM_NAME>>,
+        }
+
+        #[allow(dead_code)]
+        #[allow(non_camel_case_types)]
+        impl<__ITEM_NAME> #yield_name<__ITEM_NAME> {
+            pub fn new(steps: Vec<Option<__ITEM_NAME>>) -> Self {
+                #yield_name { steps: steps.into_iter().collect() }
+            }
+        }
+
+        // Items are never pinned in place, they are only moved out of the queue.
+        #[allow(non_camel_case_types)]
+        impl<__ITEM_NAME> ::std::marker::Unpin for #yield_name<__ITEM_NAME> {}
+
+        #[allow(non_camel_case_types)]
+        impl<__ITEM_NAME> ::std::iter::Iterator for #yield_name<__ITEM_NAME> {
+            type Item = __ITEM_NAME;
+
+            fn next(&mut self) -> Option<__ITEM_NAME> {
+                while let Some(step) = self.steps.pop_front() {
+                    if step.is_some() {
+                        return step;
+                    }
+                }
+
+                None
+            }
+        }
+
+        #[allow(dead_code)]
+        #[allow(non_camel_case_types)]
+        #pubtok struct #state_name<__RESULT_NAME> {
+            value: Option<__RESULT_NAME>,
+            waker: Option<::std::task::Waker>,
+            called: bool,
+            polled: bool,
+            completed: bool,
+            cancelled: bool,
+        }
+
+        // Handle returned by 'deferred', used by a test to resolve the pending call.
+        #[allow(dead_code)]
+        #[allow(non_camel_case_types)]
+        #pubtok struct #trigger_name<__RESULT_NAME> {
+            state: ::std::sync::Arc<::std::sync::Mutex<#state_name<__RESULT_NAME>>>,
+        }
+
+        #[allow(dead_code)]
+        #[allow(non_camel_case_types)]
+        impl<__RESULT_NAME> #trigger_name<__RESULT_NAME> {
+            pub fn complete(&self, retval: __RESULT_NAME) {
+                let waker = {
+                    let mut state = self.state.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                    if state.completed {
+                        panic!("Attempting to complete a deferred method that has already been completed");
+                    }
+                    state.completed = true;
+                    state.value = Some(retval);
+                    state.waker.take()
+                };
+
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            }
+
+            // The method has been called, and its future handed out.
+            pub fn is_called(&self) -> bool {
+                self.state.lock().unwrap_or_else(::std::sync::PoisonError::into_inner).called
+            }
+
+            // The future has been polled at least once without being ready.
+            pub fn is_pending(&self) -> bool {
+                let state = self.state.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                state.polled && !state.completed && !state.cancelled
+            }
+
+            // The future was dropped before it could return a completed value.
+            pub fn is_cancelled(&self) -> bool {
+                self.state.lock().unwrap_or_else(::std::sync::PoisonError::into_inner).cancelled
+            }
+        }
+
+        #[allow(dead_code)]
+        #[allow(non_camel_case_types)]
+        #pubtok struct #deferred_name<__RESULT_NAME> {
+            state: ::std::sync::Arc<::std::sync::Mutex<#state_name<__RESULT_NAME>>>,
+        }
+
+        #[allow(non_camel_case_types)]
+        impl<__RESULT_NAME> ::std::future::Future for #deferred_name<__RESULT_NAME> {
+            type Output = __RESULT_NAME;
+
+            fn poll(self: ::std::pin::Pin<&mut Self>, cx: &mut ::std::task::Context) -> ::std::task::Poll<__RESULT_NAME> {
+                let mut state = self.state.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                match state.value.take() {
+                    Some(value) => ::std::task::Poll::Ready(value),
+                    None => {
+                        state.polled = true;
+                        state.waker = Some(cx.waker().clone());
+                        ::std::task::Poll::Pending
+                    }
+                }
+            }
+        }
+
+        #[allow(non_camel_case_types)]
+        impl<__RESULT_NAME> ::std::ops::Drop for #deferred_name<__RESULT_NAME> {
+            fn drop(&mut self) {
+                let mut state = self.state.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                if !state.completed || state.value.is_some() {
+                    state.cancelled = true;
+                }
+            }
         }
 
         #[allow(dead_code)]
@@ -921,20 +4013,21 @@ fn generate_mock_method_body(pubtok: &proc_macro2::TokenStream, mock_method_name
         impl<__RESULT_NAME> ::std::ops::Drop for #mock_method_name<__RESULT_NAME> {
             fn drop(&mut self) {
                 if let Some(min_calls) = self.min_calls {
-                    
-                    // When using API like "called_once", if the user calls a maximum number of times,
-                    // Drop may still be called, and we will be unable to get a lock on current_num.
-                    // In this case, just silently continue, as we are already in a panic, and a
-                    // double panic will cause rust to fail to run our tests.
-                    if let Ok(value) = self.current_num.lock() {
-                        let current_num = *value;
-                        // If we have exceeded our max number of calls, we are already panicing
-                        // And we don't want to double panic
-                        if current_num - 1 < min_calls {
+                    // A failed call, such as one past 'called_at_most', may have poisoned
+                    // current_num. The count it holds is still right.
+                    let current_num = *self.current_num.lock().unwrap_or_else(::std::sync::PoisonError::into_inner);
+                    if current_num - 1 < min_calls {
+                        // A mock dropped while unwinding, such as the mock of a scope, can
+                        // only report its failure, as a second panic would abort the tests.
+                        if ::std::thread::panicking() {
+                            eprintln!("Method failed 'called at least', current number of calls is {}, minimum is {}",
+                                      current_num,
+                                      min_calls);
+                        } else {
                             panic!("Method failed 'called at least', current number of calls is {}, minimum is {}",
                                    current_num,
-                                   min_calls);                        
-                        } 
+                                   min_calls);
+                        }
                     }
                 }
             }
@@ -942,30 +4035,141 @@ fn generate_mock_method_body(pubtok: &proc_macro2::TokenStream, mock_method_name
     }
 }
 
+// #[async_trait] rewrites the methods of a trait, so our mock has to see its output.
+// When #[mock] is applied first, we emit the trait again with the attributes swapped.
+fn expand_async_trait_first(trait_block: &syn::ItemTrait, attr_ts: &proc_macro2::TokenStream) -> Option<proc_macro2::TokenStream> {
+    let position = trait_block.attrs.iter().position(|attr| {
+        attr.path.segments.last().is_some_and(|segment| segment.ident == "async_trait")
+    });
+
+    position.map(|index| {
+        let mut trait_block = trait_block.clone();
+        let async_trait = trait_block.attrs.remove(index);
+        quote! {
+            #async_trait
+            #[::mock_derive::mock(#attr_ts)]
+            #trait_block
+        }
+    })
+}
+
 #[proc_macro_attribute]
 pub fn mock(_attr_ts: TokenStream, impl_ts: TokenStream) -> TokenStream {
+    let args = _attr_ts.clone();
+    let args = parse_macro_input!(args as MockArgs);
+    let cfg = &args.cfg;
     let input = parse_macro_input!(impl_ts as MockInput);
-    let raw_item = input.item;
+    let mut raw_item = input.item;
 
+    if let syn::Item::Trait(ref trait_block) = raw_item {
+        if let Some(reordered) = expand_async_trait_first(trait_block, &_attr_ts.clone().into()) {
+            return reordered.into();
+        }
+    }
+
+    if let Some(ref failures) = args.failures {
+        if !matches!(raw_item, syn::Item::ForeignMod(_)) {
+            return syn::Error::new_spanned(failures, "Failure modes only apply to extern blocks")
+                .to_compile_error()
+                .into();
+        }
+    }
+
+    if let Some(ref preload) = args.preload {
+        if !matches!(raw_item, syn::Item::ForeignMod(_)) {
+            return syn::Error::new_spanned(preload, "Only extern blocks can be preloaded")
+                .to_compile_error()
+                .into();
+        }
+    }
+
+    let mut description = proc_macro2::TokenStream::new();
     let stream = match parse_block(&raw_item) {
-        Mockable::ForeignFunctions(impl_block) => {
-            parse_foreign_functions(impl_block, &raw_item)
+        // A preloaded library takes the place of the real one, so the block is left out
+        // wherever the library is built.
+        Mockable::ForeignFunctions(impl_block) => match args.preload {
+            Some(ref preload) => parse_preload_functions(&impl_block, &args, preload),
+            None => parse_foreign_functions(impl_block, &raw_item, &args),
+        },
+
+        Mockable::Fn(fn_item) => {
+            parse_fn(fn_item, &args)
+        },
+
+        Mockable::Mod(mod_item) => {
+            parse_mod(mod_item, &args)
+        },
+
+        Mockable::Callback(type_item) => {
+            parse_callback(type_item, &args)
+        },
+
+        Mockable::Trait(mut trait_block) => {
+            description = generate_description(&trait_block, cfg);
+            if let Err(err) = skip_supertraits(&mut trait_block, &args.skip).and_then(|_| check_io_supertraits(&trait_block)) {
+                return err.to_compile_error().into();
+            }
+            // The mock is generated by __mock_resolved once every supertrait has been described.
+            let mock = match resolve_next_supertrait(&_attr_ts.into(), &trait_block, &[]) {
+                Some(resolve) => resolve,
+                None => {
+                    let target = MockTarget::for_trait(&trait_block, &args);
+                    parse_trait(trait_block, &[], &target)
+                },
+            };
+
+            quote! {
+                #raw_item
+                #mock
+            }
+        },
+
+        Mockable::Impl(impl_block) => {
+            let mock = parse_impl(impl_block, &args);
+            // The original methods go unused wherever the mock is swapped in.
+            quote! {
+                #[allow(dead_code)]
+                #raw_item
+                #mock
+            }
         },
 
-        Mockable::Trait(trait_block) => {
-            parse_trait(trait_block, &raw_item)
+        Mockable::Use(mut use_item) => {
+            match swap_use(&use_item.tree, &args) {
+                Ok(tree) => use_item.tree = tree,
+                Err(err) => return err.to_compile_error().into(),
+            }
+            quote! { #use_item }
         }
     };
 
+    // Markers such as #[mock(closes_handle)] are only read by the mock.
+    if let syn::Item::ForeignMod(ref mut block) = raw_item {
+        for item in block.items.iter_mut() {
+            if let syn::ForeignItem::Fn(ref mut fn_item) = *item {
+                fn_item.attrs.retain(|attr| !attr.path.is_ident("mock"));
+            }
+        }
+    }
+    if let syn::Item::Mod(syn::ItemMod { content: Some((_, ref mut items)), .. }) = raw_item {
+        for item in items.iter_mut() {
+            if let syn::Item::Fn(ref mut fn_item) = *item {
+                fn_item.attrs.retain(|attr| !attr.path.is_ident("mock"));
+            }
+        }
+    }
+
     let final_output = quote! {
-        #[cfg(test)]
+        #description
+
+        #[cfg(#cfg)]
         macro_rules! mock_generate {
             () => {
                 #stream
             }
         }
 
-       #[cfg(not(test))]
+       #[cfg(not(#cfg))]
        macro_rules! mock_generate {
           () => {
                 #raw_item
@@ -977,3 +4181,82 @@ pub fn mock(_attr_ts: TokenStream, impl_ts: TokenStream) -> TokenStream {
 
     final_output.into()
 }
+
+#[proc_macro]
+pub fn mock_trait(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as MockTraitInput);
+    let trait_path = &input.trait_path;
+    let ref args = input.args;
+    let cfg = &args.cfg;
+    let trait_block = syn::ItemTrait {
+        attrs: Vec::new(),
+        vis: input.vis.clone(),
+        unsafety: input.unsafety,
+        auto_token: None,
+        trait_token: Default::default(),
+        ident: trait_path.segments.last().unwrap().ident.clone(),
+        generics: input.generics.clone(),
+        colon_token: None,
+        supertraits: syn::punctuated::Punctuated::new(),
+        brace_token: Default::default(),
+        items: input.items.clone(),
+    };
+
+    // The signatures are checked against the real trait by the impl of the mock.
+    let mut target = MockTarget::for_trait(&trait_block, args);
+    target.trait_ref = Some(quote! { #trait_path });
+    let stream = parse_trait(trait_block, &[], &target);
+
+    let final_output = quote! {
+        #[cfg(#cfg)]
+        macro_rules! mock_generate {
+            () => {
+                #stream
+            }
+        }
+
+        #[cfg(not(#cfg))]
+        macro_rules! mock_generate {
+            () => {}
+        }
+
+        mock_generate!();
+    };
+
+    final_output.into()
+}
+
+// Called by the description macros of supertraits, see 'generate_description'.
+#[doc(hidden)]
+#[proc_macro]
+pub fn __mock_resolved(input: TokenStream) -> TokenStream {
+    let mut groups = proc_macro2::TokenStream::from(input).into_iter().map(|tree| match tree {
+        proc_macro2::TokenTree::Group(group) => group.stream(),
+        _ => panic!("__mock_resolved is an internal macro of mock_derive, and should not be called directly."),
+    });
+
+    let attr_ts = groups.next().unwrap();
+    let trait_block = syn::parse2::<syn::ItemTrait>(groups.next().unwrap()).unwrap();
+    let descriptions: Vec<_> = groups.collect();
+    if let Some(resolve) = resolve_next_supertrait(&attr_ts, &trait_block, &descriptions) {
+        return resolve.into();
+    }
+
+    let mut supertraits = Vec::new();
+    for (bound, description) in described_supertraits(&trait_block).into_iter().zip(descriptions) {
+        match syn::parse2::<syn::ItemTrait>(description) {
+            Ok(base) => supertraits.push(base),
+            Err(_) => {
+                let err = syn::Error::new_spanned(&bound.path, "The supertrait resolved to a macro that does not describe a mocked trait. Check that no other macro with the name of the supertrait is in scope");
+                return err.to_compile_error().into();
+            },
+        }
+    }
+
+    let args = match syn::parse2::<MockArgs>(attr_ts) {
+        Ok(args) => args,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let target = MockTarget::for_trait(&trait_block, &args);
+    parse_trait(trait_block, &supertraits, &target).into()
+}
@@ -0,0 +1,17 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+int main(void) {
+    srand(7);
+    int first = rand();
+    int second = rand();
+    int third = rand();
+    printf("%d %d %d\n", first, second, third);
+    printf("%d\n", abs(-3));
+    printf("%p\n", (void *) strerror(2));
+    double ratio = drand48();
+    double next_ratio = drand48();
+    printf("%.1f %.1f\n", ratio, next_ratio);
+    return 0;
+}
@@ -0,0 +1,14 @@
+use mock_derive::mock;
+use std::os::raw::{c_char, c_int};
+
+// Replaces functions of libc, which the program of the test calls, when built with
+// the preload feature.
+#[allow(dead_code)]
+#[mock(preload = "FIXTURE", cfg(feature = "preload"))]
+extern "C" {
+    fn abs(value: c_int) -> c_int;
+    fn rand() -> c_int;
+    fn srand(seed: u32);
+    fn drand48() -> f64;
+    fn strerror(code: c_int) -> *mut c_char;
+}
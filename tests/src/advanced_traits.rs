@@ -23,9 +23,12 @@ SOFTWARE.
 */
 
 use mock_derive::mock;
-use export;
+use crate::export;
 
 use std::fmt::Debug;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
 
 #[mock]
 trait Base {
@@ -47,6 +50,16 @@ trait SelfOwnership {
     fn as_owned(self) -> usize;
 }
 
+#[mock]
+trait Plugin {
+    fn finish(self: Box<Self>) -> usize;
+    fn share(self: Rc<Self>) -> usize;
+    fn share_sync(self: Arc<Self>) -> usize;
+    fn poll_state(self: Pin<&mut Self>, step: usize) -> usize;
+    fn peek_state(self: Pin<&Self>) -> usize;
+    fn borrowed(self: &Self) -> usize;
+}
+
 #[mock]
 unsafe trait UnsafeTrait {
     unsafe fn this_is_not_safe(&mut self);
@@ -75,6 +88,98 @@ trait ExportInherited : export::ExportTrait {
 
 }
 
+// Supertraits may be declared after the traits deriving from them, and named through a use.
+#[mock]
+trait EarlyDerived : late::LateBase {
+    fn early(&self) -> usize;
+}
+
+mod late {
+    use mock_derive::mock;
+
+    #[mock]
+    pub trait LateBase {
+        fn late(&self) -> usize;
+    }
+}
+
+use self::late::LateBase;
+
+// Traits with the same name in different modules are told apart by the path in the bound.
+mod first_store {
+    use mock_derive::mock;
+
+    #[mock]
+    pub trait Store {
+        fn first(&self) -> u8;
+    }
+}
+
+mod second_store {
+    use mock_derive::mock;
+
+    #[mock]
+    pub trait Store {
+        fn second(&self) -> u16;
+    }
+}
+
+// An identical trait in yet another module.
+mod third_store {
+    use mock_derive::mock;
+
+    #[mock]
+    pub trait Store {
+        fn first(&self) -> u8;
+    }
+}
+
+#[mock]
+trait FirstCache : first_store::Store {
+}
+
+#[mock]
+trait ThirdCache : third_store::Store {
+}
+
+#[mock]
+trait SecondCache : second_store::Store {
+}
+
+#[mock]
+trait ImportedDerived : LateBase {
+}
+
+// A supertrait without a mock is left out of the mock, and implemented for it by hand.
+trait Unmocked {
+    fn manual(&self) -> u8;
+}
+
+#[mock(skip(Unmocked))]
+trait Skipping : Unmocked + Base {
+    fn mocked(&self) -> u8;
+}
+
+#[cfg(test)]
+impl Unmocked for MockSkipping {
+    fn manual(&self) -> u8 {
+        7
+    }
+}
+
+// Standard library supertraits are implemented by hand too, as they have no mock.
+#[mock]
+trait Named : AsRef<str> + Base {
+    fn mocked(&self) -> u8;
+}
+
+#[cfg(test)]
+impl AsRef<str> for MockNamed {
+    fn as_ref(&self) -> &str {
+        "named"
+    }
+}
+
 #[mock]
 trait StaticMethod {
     fn st_method() -> usize;
@@ -96,15 +201,31 @@ trait DebuggedTrait : Debug {
     fn foo(&self);
 }
 
-// @TODO support
-/*
+#[mock]
 trait BaseG<T> {
-...
-};
+    fn get(&self, index: usize) -> T;
+}
+
+#[mock]
 trait DerivedG : BaseG<usize> {
-...
-};
-*/
+    fn len(&self) -> usize;
+}
+
+#[mock]
+trait DerivedWrapped<T> : BaseG<Vec<T>> {
+    fn wrap(&self, t: T) -> Vec<T>;
+}
+
+#[mock]
+trait BaseBounded<'a, T: Clone, U = Vec<T>> where T: PartialEq + 'a {
+    fn split(&self, input: &'a str) -> U;
+    fn first(&self, items: &'a [T]) -> Option<&'a T>;
+}
+
+#[mock]
+trait DerivedBounded<'a> : BaseBounded<'a, String> {
+    fn name(&self) -> &'a str;
+}
 
 #[test]
 fn print_trait() {
@@ -131,6 +252,106 @@ fn mock_derived() {
     assert!(mock_derived.add(0, 0) == 25);
 }
 
+#[test]
+fn mock_supertrait_declared_later() {
+    let mut mock = MockEarlyDerived::new();
+    let late = mock.method_late()
+        .set_result(2);
+    let early = mock.method_early()
+        .set_result(1);
+
+    mock.set_late(late);
+    mock.set_early(early);
+    assert!(mock.early() == 1);
+    assert!(mock.late() == 2);
+
+    let mut imported = MockImportedDerived::new();
+    let late = imported.method_late()
+        .set_result(3);
+
+    imported.set_late(late);
+    assert!(imported.late() == 3);
+}
+
+#[test]
+fn mock_supertraits_with_same_name() {
+    use self::first_store::Store as FirstStore;
+    use self::second_store::Store as SecondStore;
+    use self::third_store::Store as ThirdStore;
+
+    let mut first = MockFirstCache::new();
+    let method = first.method_first()
+        .set_result(1);
+    first.set_first(method);
+
+    let mut second = MockSecondCache::new();
+    let method = second.method_second()
+        .set_result(2);
+    second.set_second(method);
+
+    let mut third = MockThirdCache::new();
+    let method = third.method_first()
+        .set_result(3);
+    third.set_first(method);
+
+    assert!(first.first() == 1);
+    assert!(second.second() == 2);
+    assert!(third.first() == 3);
+}
+
+#[test]
+fn mock_std_supertrait_by_hand() {
+    let mut mock = MockNamed::new();
+    let method = mock.method_mocked()
+        .set_result(4);
+    mock.set_mocked(method);
+
+    assert!(mock.mocked() == 4);
+    assert!(mock.as_ref() == "named");
+}
+
+#[test]
+fn mock_generic_supertrait() {
+    let mut mock = MockDerivedG::new();
+    let get = mock.method_get()
+        .return_result_of(|| 7);
+    let len = mock.method_len()
+        .set_result(1);
+
+    mock.set_get(get);
+    mock.set_len(len);
+    assert!(mock.get(0) == 7);
+    assert!(mock.len() == 1);
+}
+
+#[test]
+fn mock_generic_supertrait_from_param() {
+    let mut mock = MockDerivedWrapped::<u8>::new();
+    let get = mock.method_get()
+        .set_result(vec![1, 2]);
+
+    mock.set_get(get);
+    assert!(mock.get(0) == vec![1, 2]);
+}
+
+#[test]
+fn mock_generic_supertrait_defaults() {
+    let mut mock = MockDerivedBounded::new();
+    let split = mock.method_split()
+        .set_result(vec![String::from("a"), String::from("b")]);
+    let first = mock.method_first()
+        .set_result(None);
+    let name = mock.method_name()
+        .set_result("bounded");
+
+    mock.set_split(split);
+    mock.set_first(first);
+    mock.set_name(name);
+    assert!(mock.split("a b") == vec!["a", "b"]);
+    assert!(mock.first(&[]) == None);
+    assert!(mock.name() == "bounded");
+}
+
 #[test]
 fn mock_self_owned() {
     let mut mock = MockSelfOwnership::new();
@@ -168,6 +389,91 @@ fn mock_self_owned_no_fallback() {
     assert!(mock.as_owned() == 35);
 }
 
+#[cfg(test)]
+struct CountingPlugin {
+    count: usize,
+}
+
+#[cfg(test)]
+impl Plugin for CountingPlugin {
+    fn finish(self: Box<Self>) -> usize {
+        self.count
+    }
+
+    fn share(self: Rc<Self>) -> usize {
+        self.count
+    }
+
+    fn share_sync(self: Arc<Self>) -> usize {
+        self.count
+    }
+
+    fn poll_state(mut self: Pin<&mut Self>, step: usize) -> usize {
+        self.count += step;
+        self.count
+    }
+
+    fn peek_state(self: Pin<&Self>) -> usize {
+        self.count
+    }
+
+    fn borrowed(self: &Self) -> usize {
+        self.count
+    }
+}
+
+#[test]
+fn typed_self_receivers() {
+    let mut mock = MockPlugin::new();
+    let finish = mock.method_finish()
+        .called_once()
+        .set_result(1);
+    let share = mock.method_share()
+        .set_result(2);
+    let share_sync = mock.method_share_sync()
+        .set_result(3);
+    let poll_state = mock.method_poll_state()
+        .returns_arg(0);
+    let borrowed = mock.method_borrowed()
+        .set_result(5);
+
+    mock.set_finish(finish);
+    mock.set_share(share);
+    mock.set_share_sync(share_sync);
+    mock.set_poll_state(poll_state);
+    mock.set_borrowed(borrowed);
+
+    assert!(mock.borrowed() == 5);
+    assert!(Pin::new(&mut mock).poll_state(4) == 4);
+    let mock = Arc::new(mock);
+    assert!(mock.clone().share_sync() == 3);
+    let mock = Arc::try_unwrap(mock).unwrap();
+    let mock = Rc::new(mock);
+    assert!(mock.clone().share() == 2);
+    let mock = Rc::try_unwrap(mock).unwrap();
+    assert!(Box::new(mock).finish() == 1);
+}
+
+#[test]
+fn typed_self_receivers_fallback() {
+    let mut mock = MockPlugin::new();
+    mock.set_fallback(CountingPlugin { count: 10 });
+
+    assert!(Pin::new(&mut mock).poll_state(5) == 15);
+    assert!(Pin::new(&mock).peek_state() == 15);
+    assert!(mock.borrowed() == 15);
+    assert!(Box::new(mock).finish() == 15);
+}
+
+#[test]
+#[should_panic(expected = "shared pointer is not supported")]
+fn typed_self_receivers_shared_fallback() {
+    let mut mock = MockPlugin::new();
+    mock.set_fallback(CountingPlugin { count: 10 });
+
+    Rc::new(mock).share();
+}
+
 #[test]
 fn unsafety_trait() {
     let mut mock = MockUnsafeTrait::new();
@@ -187,4 +493,19 @@ fn static_fn_test() {
     MockStaticMethod::set_st_method(mock);
     assert!(MockStaticMethod::st_method() == 25);
     MockStaticMethod::clear_st_method();
-}
\ No newline at end of file
+}
+
+#[test]
+fn skipped_supertrait() {
+    let mut mock = MockSkipping::new();
+    let method = mock.method_mocked()
+        .set_result(1);
+    mock.set_mocked(method);
+    let method = mock.method_add()
+        .set_result(2);
+    mock.set_add(method);
+
+    assert!(mock.mocked() == 1);
+    assert!(mock.add(0, 0) == 2);
+    assert!(mock.manual() == 7);
+}
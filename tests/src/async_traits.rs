@@ -0,0 +1,323 @@
+/*
+MIT License
+
+Copyright (c) 2020 David DeSimone
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+
+use async_trait::async_trait;
+use mock_derive::mock;
+
+use std::future::Future;
+use std::pin::Pin;
+#[cfg(test)]
+use std::sync::Arc;
+#[cfg(test)]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(test)]
+use std::task::{Context, Poll, Wake, Waker};
+#[cfg(test)]
+use std::thread::{self, Thread};
+
+// A minimal executor, so these tests do not depend on an async runtime.
+#[cfg(test)]
+fn block_on<F: Future>(future: F) -> F::Output {
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(value) => return value,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+// Records whether a future has asked to be polled again.
+#[cfg(test)]
+struct FlagWaker(AtomicBool);
+
+#[cfg(test)]
+impl Wake for FlagWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+fn poll_once<F: Future + ?Sized>(future: Pin<&mut F>, flag: &Arc<FlagWaker>) -> Poll<F::Output> {
+    let waker = Waker::from(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+    future.poll(&mut cx)
+}
+
+#[mock]
+trait Client {
+    async fn fetch(&self, id: u32) -> String;
+    async fn ping(&mut self);
+    fn ready(&self) -> impl Future<Output = bool> + Send;
+}
+
+#[mock]
+trait Clock {
+    fn now(&self) -> u64;
+}
+
+// The shape of a method after #[async_trait] has expanded it.
+#[mock]
+trait Store {
+    fn load<'life0, 'async_trait>(&'life0 self, key: u32)
+        -> Pin<Box<dyn Future<Output = Option<u32>> + Send + 'async_trait>>
+        where 'life0: 'async_trait, Self: 'async_trait;
+}
+
+// #[mock] is applied first, so it has to hand the trait to #[async_trait] before mocking it.
+#[mock]
+#[async_trait]
+trait Cache {
+    async fn get(&self, key: u32) -> Option<String>;
+    async fn put(&mut self, key: u32, value: String);
+}
+
+#[cfg(test)]
+struct MemoryCache;
+
+#[cfg(test)]
+#[async_trait]
+impl Cache for MemoryCache {
+    async fn get(&self, key: u32) -> Option<String> {
+        Some(key.to_string())
+    }
+
+    async fn put(&mut self, _key: u32, _value: String) {}
+}
+
+#[cfg(test)]
+struct MemoryStore;
+
+#[cfg(test)]
+impl Store for MemoryStore {
+    fn load<'life0, 'async_trait>(&'life0 self, key: u32)
+        -> Pin<Box<dyn Future<Output = Option<u32>> + Send + 'async_trait>>
+        where 'life0: 'async_trait, Self: 'async_trait {
+        Box::pin(async move { Some(key * 2) })
+    }
+}
+
+#[test]
+fn async_fn_set_result() {
+    let mut mock = MockClient::new();
+    let method = mock.method_fetch()
+        .called_once()
+        .set_result(String::from("response"));
+
+    mock.set_fetch(method);
+    assert!(block_on(mock.fetch(1)) == "response");
+}
+
+#[test]
+fn async_fn_return_future_of() {
+    let mut mock = MockClient::new();
+    let mut calls = 0;
+    let method = mock.method_fetch()
+        .called_ntimes(2)
+        .return_future_of(move || {
+            calls += 1;
+            async move { format!("call {}", calls) }
+        });
+
+    mock.set_fetch(method);
+    assert!(block_on(mock.fetch(1)) == "call 1");
+    assert!(block_on(mock.fetch(1)) == "call 2");
+}
+
+#[test]
+fn async_fn_no_return() {
+    let mut mock = MockClient::new();
+    let method = mock.method_ping()
+        .called_once()
+        .set_result(());
+
+    mock.set_ping(method);
+    block_on(mock.ping());
+}
+
+#[test]
+fn impl_future() {
+    let mut mock = MockClient::new();
+    let method = mock.method_ready()
+        .first_call()
+        .set_result(true)
+        .second_call()
+        .set_result(false);
+
+    mock.set_ready(method);
+    assert!(block_on(mock.ready()));
+    assert!(!block_on(mock.ready()));
+}
+
+#[test]
+fn boxed_future() {
+    let mut mock = MockStore::new();
+    let method = mock.method_load()
+        .first_call()
+        .set_result(None)
+        .returns_arg(0);
+
+    mock.set_load(method);
+    assert!(block_on(mock.load(3)) == None);
+    assert!(block_on(mock.load(3)) == Some(3));
+}
+
+#[test]
+fn boxed_future_fallback() {
+    let mut mock = MockStore::new();
+    mock.set_fallback(MemoryStore);
+    let method = mock.method_load()
+        .return_future_of(|| async { Some(1) });
+
+    assert!(block_on(mock.load(3)) == Some(6));
+    mock.set_load(method);
+    assert!(block_on(mock.load(3)) == Some(1));
+}
+
+#[test]
+fn async_trait_attribute() {
+    let mut mock = MockCache::new();
+    let method = mock.method_get()
+        .first_call()
+        .set_result(Some(String::from("cached")))
+        .second_call()
+        .set_result(None);
+    mock.set_get(method);
+
+    let method = mock.method_put()
+        .called_once()
+        .return_future_of(|| async {});
+    mock.set_put(method);
+
+    assert!(block_on(mock.get(1)) == Some(String::from("cached")));
+    assert!(block_on(mock.get(1)) == None);
+    block_on(mock.put(1, String::from("value")));
+}
+
+#[test]
+fn async_trait_attribute_fallback() {
+    let mut mock = MockCache::new();
+    mock.set_fallback(MemoryCache);
+
+    assert!(block_on(mock.get(4)) == Some(String::from("4")));
+    block_on(mock.put(4, String::from("value")));
+}
+
+#[test]
+#[should_panic(expected = "does not return a future")]
+fn return_future_of_sync_method() {
+    let mock = MockClock::new();
+    mock.method_now()
+        .return_future_of(|| async { 0 });
+}
+
+#[test]
+#[should_panic(expected = "mutally exclusive")]
+fn set_result_after_return_future_of() {
+    let mock = MockClient::new();
+    mock.method_fetch()
+        .return_future_of(|| async { String::new() })
+        .set_result(String::new());
+}
+
+#[test]
+fn deferred_pending_until_complete() {
+    let mut mock = MockClient::new();
+    let (method, trigger) = mock.method_fetch()
+        .called_once()
+        .deferred();
+
+    mock.set_fetch(method);
+    let flag = Arc::new(FlagWaker(AtomicBool::new(false)));
+    let mut future = Box::pin(mock.fetch(1));
+    assert!(poll_once(future.as_mut(), &flag).is_pending());
+    assert!(trigger.is_called());
+    assert!(trigger.is_pending());
+    assert!(poll_once(future.as_mut(), &flag).is_pending());
+
+    trigger.complete(String::from("late"));
+    assert!(flag.0.load(Ordering::SeqCst));
+    assert!(poll_once(future.as_mut(), &flag) == Poll::Ready(String::from("late")));
+    assert!(!trigger.is_cancelled());
+}
+
+#[test]
+fn deferred_complete_from_thread() {
+    let mut mock = MockStore::new();
+    let (method, trigger) = mock.method_load().deferred();
+
+    mock.set_load(method);
+    let handle = thread::spawn(move || {
+        while !trigger.is_pending() {
+            thread::yield_now();
+        }
+        trigger.complete(Some(9));
+    });
+
+    assert!(block_on(mock.load(3)) == Some(9));
+    handle.join().unwrap();
+}
+
+#[test]
+fn deferred_cancelled() {
+    let mut mock = MockClient::new();
+    let (method, trigger) = mock.method_fetch().deferred();
+
+    mock.set_fetch(method);
+    assert!(!trigger.is_called());
+    {
+        let flag = Arc::new(FlagWaker(AtomicBool::new(false)));
+        let mut future = Box::pin(mock.fetch(1));
+        assert!(poll_once(future.as_mut(), &flag).is_pending());
+    }
+
+    assert!(trigger.is_cancelled());
+    assert!(!trigger.is_pending());
+}
+
+#[test]
+#[should_panic(expected = "called more than once")]
+fn deferred_called_twice() {
+    let mut mock = MockClient::new();
+    let (method, _trigger) = mock.method_fetch().deferred();
+
+    mock.set_fetch(method);
+    let flag = Arc::new(FlagWaker(AtomicBool::new(false)));
+    let mut first = Box::pin(mock.fetch(1));
+    let _ = poll_once(first.as_mut(), &flag);
+    let mut second = Box::pin(mock.fetch(2));
+    let _ = poll_once(second.as_mut(), &flag);
+}
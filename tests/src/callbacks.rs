@@ -0,0 +1,184 @@
+/*
+MIT License
+
+Copyright (c) 2020 David DeSimone
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+
+use mock_derive::mock;
+use std::os::raw::{c_int, c_void};
+#[cfg(test)]
+use std::panic;
+
+#[mock]
+pub type OnEvent = unsafe extern "C" fn(user_data: *mut c_void, code: c_int) -> c_int;
+
+#[allow(dead_code)]
+#[mock]
+pub type OnTick = extern "C" fn(ticks: u32);
+
+// Callbacks are shared by every thread, as libraries may call them from threads of their own.
+#[allow(dead_code)]
+#[mock(name = "SharedProgress")]
+pub type OnProgress = extern "C" fn(done: u32, total: u32) -> bool;
+
+// Stands in for a C library that calls back into its users.
+#[allow(dead_code)]
+#[derive(Default)]
+struct EventLoop {
+    handlers: Vec<(OnEvent, *mut c_void)>,
+}
+
+#[allow(dead_code)]
+impl EventLoop {
+    fn register(&mut self, handler: OnEvent, user_data: *mut c_void) {
+        self.handlers.push((handler, user_data));
+    }
+
+    fn emit(&self, code: c_int) -> Vec<c_int> {
+        self.handlers.iter()
+            .map(|&(handler, user_data)| unsafe { handler(user_data, code) })
+            .collect()
+    }
+}
+
+#[test]
+fn callback_records_calls() {
+    let callback = MockOnEvent::mint(MockOnEvent::method()
+        .called_ntimes(2)
+        .return_result_of(|| 7));
+    let mut events = EventLoop::default();
+    events.register(callback.function(), callback.user_data());
+
+    assert!(events.emit(1) == vec![7]);
+    assert!(events.emit(2) == vec![7]);
+    assert!(callback.call_count() == 2);
+    assert!(callback.calls() == vec![(callback.user_data(), 1), (callback.user_data(), 2)]);
+}
+
+#[test]
+fn minted_callbacks_are_distinct() {
+    let first = MockOnEvent::mint(MockOnEvent::method().set_result(1));
+    let second = MockOnEvent::mint(MockOnEvent::method().set_result(2));
+    assert!(first.function() as usize != second.function() as usize);
+    assert!(first.user_data() != second.user_data());
+
+    let mut events = EventLoop::default();
+    events.register(first.function(), first.user_data());
+    events.register(second.function(), second.user_data());
+    assert!(events.emit(0) == vec![1, 2]);
+}
+
+#[test]
+fn router_finds_callback_by_user_data() {
+    let first = MockOnEvent::mint(MockOnEvent::method().returns_arg(1));
+    let second = MockOnEvent::mint(MockOnEvent::method().set_result(-1));
+
+    let mut events = EventLoop::default();
+    events.register(MockOnEvent::router(), second.user_data());
+    events.register(MockOnEvent::router(), first.user_data());
+    assert!(events.emit(4) == vec![-1, 4]);
+    assert!(first.call_count() == 1);
+    assert!(second.call_count() == 1);
+}
+
+#[test]
+fn failed_callback_returns_fallback() {
+    let callback = MockOnEvent::mint(MockOnEvent::method()
+        .called_at_most(1)
+        .set_result(3)
+        .fallback(-1));
+    let mut events = EventLoop::default();
+    events.register(callback.function(), callback.user_data());
+
+    assert!(events.emit(0) == vec![3]);
+    assert!(events.emit(0) == vec![-1]);
+    assert!(panic::catch_unwind(|| callback.verify()).is_err());
+    callback.verify();
+}
+
+#[test]
+fn callback_calls_another_callback() {
+    let inner = std::sync::Arc::new(MockOnEvent::mint(MockOnEvent::method()
+        .return_result_of(|| 2)));
+    let nested = std::sync::Arc::clone(&inner);
+    let outer = MockOnEvent::mint(MockOnEvent::method()
+        .return_result_of(move || {
+            let result = unsafe { (nested.function())(nested.user_data(), 1) };
+            result + nested.call_count() as c_int
+        }));
+    let mut events = EventLoop::default();
+    events.register(outer.function(), outer.user_data());
+
+    assert!(events.emit(0) == vec![3]);
+    assert!(inner.calls() == vec![(inner.user_data(), 1)]);
+}
+
+#[test]
+#[should_panic(expected = "Called a method that has been marked as 'never called'!")]
+fn dropped_callback_raises_failures() {
+    let callback = MockOnEvent::mint(MockOnEvent::method()
+        .never_called());
+    let mut events = EventLoop::default();
+    events.register(callback.function(), callback.user_data());
+
+    assert!(events.emit(0) == vec![0]);
+}
+
+#[test]
+#[should_panic(expected = "was called with the user data")]
+fn callback_checks_user_data() {
+    let callback = MockOnEvent::mint(MockOnEvent::method()
+        .set_result(0));
+    let mut events = EventLoop::default();
+    events.register(callback.function(), std::ptr::null_mut());
+
+    events.emit(0);
+}
+
+#[test]
+#[should_panic(expected = "called at least")]
+fn dropped_callback_verifies_min_calls() {
+    let _callback = MockOnEvent::mint(MockOnEvent::method()
+        .called_once()
+        .set_result(0));
+}
+
+#[test]
+fn callback_without_user_data() {
+    let callback = MockOnTick::mint(MockOnTick::method()
+        .called_ntimes(2)
+        .return_result_of(|| ()));
+    (callback.function())(5);
+    (callback.function())(6);
+    assert!(callback.calls() == vec![(5,), (6,)]);
+}
+
+#[test]
+fn global_callback_called_from_worker() {
+    let _lock = SharedProgress::lock();
+    let callback = SharedProgress::mint(SharedProgress::method()
+        .return_result_of(|| true));
+    let function = callback.function();
+
+    assert!(std::thread::spawn(move || function(1, 2)).join().unwrap());
+    assert!(callback.calls() == vec![(1, 2)]);
+}
@@ -0,0 +1,170 @@
+/*
+MIT License
+
+Copyright (c) 2020 David DeSimone
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+
+use mock_derive::mock_trait;
+
+#[cfg(test)]
+use std::io::Write;
+
+// Stands in for a crate that we can't add #[mock] to.
+pub mod shared {
+    pub trait Storage {
+        fn get(&self, key: &str) -> Option<Vec<u8>>;
+        fn put(&mut self, key: &str, value: Vec<u8>) -> bool;
+    }
+
+    pub trait Repository<K> {
+        fn find(&self, key: K) -> Option<String>;
+    }
+
+    pub trait Clock {
+        fn now() -> u64;
+    }
+
+    pub struct EmptyStorage;
+
+    impl Storage for EmptyStorage {
+        fn get(&self, _key: &str) -> Option<Vec<u8>> {
+            None
+        }
+
+        fn put(&mut self, _key: &str, _value: Vec<u8>) -> bool {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+use shared::{Clock, Repository, Storage};
+
+mock_trait! {
+    impl shared::Storage for MockStorage {
+        fn get(&self, key: &str) -> Option<Vec<u8>>;
+        fn put(&mut self, key: &str, value: Vec<u8>) -> bool;
+    }
+}
+
+mock_trait! {
+    impl<K> shared::Repository<K> for MockRepository<K> where K: 'static {
+        fn find(&self, key: K) -> Option<String>;
+    }
+}
+
+mock_trait! {
+    impl std::io::Write for MockSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize>;
+        fn flush(&mut self) -> std::io::Result<()>;
+    }
+}
+
+// The mock takes the arguments of #[mock], here to be built with the mocks feature, and
+// to be named after the name argument rather than after the trait.
+mock_trait! {
+    #[mock(name = "FakeStorage", feature = "mocks")]
+    impl shared::Storage {
+        fn get(&self, key: &str) -> Option<Vec<u8>>;
+        fn put(&mut self, key: &str, value: Vec<u8>) -> bool;
+    }
+}
+
+mock_trait! {
+    #[mock(storage = "global")]
+    impl shared::Clock {
+        fn now() -> u64;
+    }
+}
+
+#[test]
+fn mock_external_trait() {
+    let mut mock = MockStorage::new();
+    let get = mock.method_get()
+        .called_once()
+        .set_result(Some(vec![1, 2]));
+    let put = mock.method_put()
+        .return_result_of(|| true);
+
+    mock.set_get(get);
+    mock.set_put(put);
+    assert!(mock.get("key") == Some(vec![1, 2]));
+    assert!(mock.put("key", vec![3]));
+}
+
+#[test]
+fn mock_external_trait_fallback() {
+    let mut mock = MockStorage::new();
+    mock.set_fallback(shared::EmptyStorage);
+    let put = mock.method_put()
+        .set_result(true);
+
+    mock.set_put(put);
+    assert!(mock.get("key").is_none());
+    assert!(mock.put("key", vec![3]));
+}
+
+#[test]
+fn mock_external_generic_trait() {
+    let mut mock = MockRepository::<u32>::new();
+    let find = mock.method_find()
+        .set_result(Some(String::from("found")));
+
+    mock.set_find(find);
+    assert!(mock.find(1) == Some(String::from("found")));
+}
+
+#[test]
+fn mock_std_trait() {
+    let mut mock = MockSink::new();
+    let write = mock.method_write()
+        .set_result(Ok(4));
+    let flush = mock.method_flush()
+        .called_once()
+        .set_result(Ok(()));
+
+    mock.set_write(write);
+    mock.set_flush(flush);
+    assert!(mock.write_all(b"data").is_ok());
+    assert!(mock.flush().is_ok());
+}
+
+#[test]
+fn mock_external_trait_with_arguments() {
+    let mut mock = FakeStorage::new();
+    let get = mock.method_get()
+        .set_result(None);
+
+    mock.set_get(get);
+    assert!(mock.get("key").is_none());
+}
+
+#[test]
+fn mock_external_trait_global_storage() {
+    let _lock = MockClock::lock_statics();
+    let method = MockClock::method_now()
+        .return_result_of(|| 42);
+
+    MockClock::set_now(method);
+    let now = std::thread::spawn(MockClock::now).join().unwrap();
+    assert!(now == 42);
+}
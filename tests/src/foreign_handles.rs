@@ -0,0 +1,128 @@
+/*
+MIT License
+
+Copyright (c) 2020 David DeSimone
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+
+use mock_derive::mock;
+
+// Extern types need the extern_types feature outside of tests, where they are not mocked.
+#[cfg(test)]
+#[mock(name = "ExternDb")]
+extern "C" {
+    pub type mock_db;
+    fn mock_db_open(path: *const u8) -> *mut mock_db;
+    fn mock_db_exec(db: *mut mock_db, sql: *const u8) -> i32;
+    #[mock(closes_handle)]
+    fn mock_db_close(db: *mut mock_db) -> i32;
+}
+
+#[repr(C)]
+pub struct Opaque {
+    _private: [u8; 0],
+}
+
+#[allow(dead_code)]
+#[mock(name = "ExternOpaque")]
+extern "C" {
+    fn mock_opaque_new() -> *mut Opaque;
+    #[mock(closes_handle)]
+    fn mock_opaque_free(opaque: *mut Opaque);
+}
+
+#[cfg(test)]
+fn run_query(sql: &str) -> i32 {
+    unsafe {
+        let db = mock_db_open(b"test.db\0".as_ptr());
+        let result = mock_db_exec(db, sql.as_ptr());
+        mock_db_close(db);
+        result
+    }
+}
+
+#[cfg(test)]
+fn setup_db() -> *mut mock_db {
+    let handle = ExternDb::new_handle();
+    let open = ExternDb::method_mock_db_open()
+        .set_result(handle);
+    let exec = ExternDb::method_mock_db_exec()
+        .set_result(0);
+    let close = ExternDb::method_mock_db_close()
+        .set_result(0);
+
+    ExternDb::set_mock_db_open(open);
+    ExternDb::set_mock_db_exec(exec);
+    ExternDb::set_mock_db_close(close);
+    handle
+}
+
+#[test]
+fn handle_lifecycle() {
+    let handle = setup_db();
+
+    assert!(ExternDb::is_open_handle(handle));
+    assert!(run_query("select 1") == 0);
+    assert!(!ExternDb::is_open_handle(handle));
+    ExternDb::verify_handles();
+}
+
+#[test]
+fn opaque_struct_handles() {
+    let handle = ExternOpaque::new_handle();
+    let new = ExternOpaque::method_mock_opaque_new()
+        .set_result(handle);
+    let free = ExternOpaque::method_mock_opaque_free()
+        .called_once()
+        .set_result(());
+
+    ExternOpaque::set_mock_opaque_new(new);
+    ExternOpaque::set_mock_opaque_free(free);
+    unsafe {
+        let opaque = mock_opaque_new();
+        assert!(ExternOpaque::open_handles() == 1);
+        mock_opaque_free(opaque);
+    }
+    ExternOpaque::verify_handles();
+}
+
+#[test]
+#[should_panic]
+fn leaked_handle() {
+    setup_db();
+    unsafe { mock_db_open(b"test.db\0".as_ptr()); }
+    ExternDb::verify_handles();
+}
+
+#[test]
+#[should_panic]
+fn handle_closed_twice() {
+    let handle = setup_db();
+    run_query("select 1");
+    ExternDb::close_handle(handle);
+}
+
+#[test]
+#[should_panic]
+fn unknown_handle_closed() {
+    let mut value = 0u64;
+    ExternDb::close_handle(&mut value as *mut u64);
+}
@@ -1,7 +1,7 @@
 use mock_derive::mock;
 
 #[allow(dead_code)]
-#[mock]
+#[mock(failures = "unwind")]
 extern "C" {
     pub fn c_double(x: isize) -> isize;
     pub fn c_div(x: isize, y: isize) -> isize;
@@ -68,3 +68,110 @@ fn extern_min_calls() {
     // Needed to trigger 'min call' related errors for extern fns
     ExternCMocks::clear_c_double();
 }
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LibConfig {
+    pub verbose: bool,
+    pub level: u32,
+}
+
+#[allow(dead_code)]
+#[mock(name = "ExternGlobals")]
+extern "C" {
+    static mock_lib_version: u32;
+    static mut mock_lib_config: LibConfig;
+}
+
+#[cfg(test)]
+fn lib_level() -> u32 {
+    unsafe { mock_lib_config.level }
+}
+
+// Each static is only used by one test, so no other thread touches it.
+#[test]
+fn extern_static_test() {
+    unsafe {
+        ExternGlobals::set_mock_lib_version(3);
+        assert!(mock_lib_version == 3);
+        assert!(*ExternGlobals::get_mock_lib_version() == 3);
+    }
+}
+
+#[test]
+fn extern_static_mut_test() {
+    unsafe { ExternGlobals::set_mock_lib_config(LibConfig { verbose: true, level: 2 }); }
+    assert!(lib_level() == 2);
+
+    unsafe {
+        mock_lib_config.level = 4;
+        assert!(ExternGlobals::get_mock_lib_config().level == 4);
+    }
+}
+
+// Variadic functions are mocked through their fixed arguments, and ignore the rest.
+#[allow(dead_code)]
+#[mock(name = "ExternLog")]
+extern "C" {
+    fn mock_log(level: i32, fmt: *const u8, ...) -> i32;
+}
+
+#[test]
+fn extern_variadic_test() {
+    let mock = ExternLog::method_mock_log()
+        .first_call()
+        .set_result(7)
+        .returns_arg(0);
+
+    ExternLog::set_mock_log(mock);
+    unsafe {
+        assert!(mock_log(1, b"%d %s\0".as_ptr(), 5i32, b"five\0".as_ptr()) == 7);
+        assert!(mock_log(2, b"done\0".as_ptr()) == 2);
+    }
+}
+
+// The libraries are only named in tests, where the blocks are mocked and never linked.
+#[allow(dead_code)]
+#[cfg_attr(test, link(name = "zip"))]
+#[mock]
+extern "C" {
+    fn zip_entries(archive: *const u8) -> usize;
+}
+
+#[allow(dead_code)]
+#[cfg_attr(test, link(name = "tar-ball"))]
+#[mock]
+extern "C" {
+    fn tar_entries(archive: *const u8) -> usize;
+}
+
+#[test]
+fn extern_link_name_test() {
+    let zip = ZipMocks::method_zip_entries()
+        .set_result(2);
+    let tar = TarBallMocks::method_tar_entries()
+        .set_result(3);
+
+    ZipMocks::set_zip_entries(zip);
+    TarBallMocks::set_tar_entries(tar);
+    unsafe {
+        assert!(zip_entries(b"a.zip\0".as_ptr()) == 2);
+        assert!(tar_entries(b"a.tar\0".as_ptr()) == 3);
+    }
+}
+
+#[allow(dead_code)]
+#[mock(name = "ExternHooks")]
+extern "C" {
+    fn hook_square(x: i32) -> i32;
+}
+
+#[test]
+fn extern_c_function_pointer() {
+    let method = ExternHooks::method_hook_square()
+        .set_result(9);
+
+    ExternHooks::set_hook_square(method);
+    let hook: unsafe extern "C" fn(i32) -> i32 = hook_square;
+    unsafe { assert!(hook(3) == 9); }
+}
@@ -1,8 +1,8 @@
 #[allow(unused_imports)]
-use foriegn_functions::{c_double, c_div};
+use crate::foriegn_functions::{c_double, c_div};
 
 #[cfg(test)]
-use foriegn_functions::ExternCMocks;
+use crate::foriegn_functions::ExternCMocks;
 
 #[test]
 fn other_mod_test() {
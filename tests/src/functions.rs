@@ -0,0 +1,109 @@
+/*
+MIT License
+
+Copyright (c) 2020 David DeSimone
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+
+use mock_derive::mock;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Config {
+    pub name: String,
+    pub retries: u32,
+}
+
+#[mock]
+pub fn read_config(path: &str) -> Config {
+    Config { name: path.to_string(), retries: 1 }
+}
+
+#[mock]
+fn factorial(n: u64) -> u64 {
+    if n <= 1 { 1 } else { n * factorial(n - 1) }
+}
+
+#[mock]
+fn clamp(value: i64, low: i64, high: i64) -> i64 {
+    value.max(low).min(high)
+}
+
+#[mock(name = "FakeShutdown")]
+fn shutdown(code: i32) {
+    panic!("Shut down with {}", code);
+}
+
+pub fn load_retries() -> u32 {
+    read_config("app.toml").retries
+}
+
+#[test]
+fn mock_function() {
+    let method = MockReadConfig::method_read_config()
+        .called_once()
+        .set_result(Config { name: String::from("test"), retries: 5 });
+
+    MockReadConfig::set_read_config(method);
+    assert!(load_retries() == 5);
+}
+
+#[test]
+fn unset_function_calls_original() {
+    assert!(read_config("app.toml").name == "app.toml");
+    assert!(factorial(5) == 120);
+}
+
+#[test]
+fn clear_function() {
+    let method = MockFactorial::method_factorial()
+        .set_result(0);
+
+    MockFactorial::set_factorial(method);
+    assert!(factorial(5) == 0);
+    MockFactorial::clear_factorial();
+    assert!(factorial(5) == 120);
+}
+
+#[test]
+fn renamed_function_mock() {
+    let method = FakeShutdown::method_shutdown()
+        .called_once()
+        .set_result(());
+
+    FakeShutdown::set_shutdown(method);
+    shutdown(1);
+}
+
+#[test]
+fn function_with_several_arguments() {
+    assert!(clamp(12, 0, 10) == 10);
+
+    let method = MockClamp::method_clamp()
+        .first_call()
+        .set_result(-1)
+        .returns_arg(2);
+
+    MockClamp::set_clamp(method);
+    assert!(clamp(12, 0, 10) == -1);
+    assert!(clamp(12, 0, 10) == 10);
+    MockClamp::clear_clamp();
+    assert!(clamp(-5, 0, 10) == 0);
+}
@@ -0,0 +1,75 @@
+/*
+MIT License
+
+Copyright (c) 2020 David DeSimone
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+
+use mock_derive::mock;
+
+// Built with --features mocks, MockClock is available to other crates.
+#[mock(feature = "mocks")]
+pub trait Clock {
+    fn now(&self) -> u64;
+}
+
+// Only compiles when MockClock is generated outside of tests, by building the crate
+// with --features mocks.
+#[cfg(all(not(test), feature = "mocks"))]
+pub fn fixed_clock(now: u64) -> MockClock {
+    let mut mock = MockClock::new();
+    let method = mock.method_now()
+        .set_result(now);
+    mock.set_now(method);
+    mock
+}
+
+#[mock(cfg(any(test, debug_assertions)))]
+pub trait Timer {
+    fn elapsed(&self) -> u64;
+}
+
+// Only compiles when MockTimer is generated outside of tests.
+#[cfg(debug_assertions)]
+pub fn new_timer() -> MockTimer {
+    MockTimer::new()
+}
+
+#[test]
+fn feature_gated_mock() {
+    let mut mock = MockClock::new();
+    let method = mock.method_now()
+        .set_result(10);
+
+    mock.set_now(method);
+    assert!(mock.now() == 10);
+}
+
+#[test]
+fn cfg_gated_mock() {
+    let mut mock = new_timer();
+    let method = mock.method_elapsed()
+        .called_once()
+        .set_result(5);
+
+    mock.set_elapsed(method);
+    assert!(mock.elapsed() == 5);
+}
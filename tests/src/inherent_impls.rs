@@ -0,0 +1,169 @@
+/*
+MIT License
+
+Copyright (c) 2020 David DeSimone
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+
+pub mod http {
+    use mock_derive::mock;
+
+    #[derive(Clone, PartialEq, Debug)]
+    pub struct Response {
+        pub status: u16,
+    }
+
+    pub struct HttpClient {
+        base: String,
+        timeout: u64,
+    }
+
+    #[mock]
+    impl HttpClient {
+        pub const RETRIES: u32 = 3;
+
+        pub fn new(base: &str) -> HttpClient {
+            HttpClient { base: base.to_string(), timeout: 30 }
+        }
+
+        pub fn get(&self, path: &str) -> Response {
+            if path.is_empty() || self.base.is_empty() {
+                Response { status: 404 }
+            } else {
+                Response { status: 200 }
+            }
+        }
+
+        pub fn timeout(&self) -> u64 {
+            self.timeout
+        }
+
+        pub fn set_timeout(&mut self, seconds: u64) {
+            self.timeout = seconds;
+        }
+
+        pub fn with_timeout(mut self, seconds: u64) -> Self {
+            self.timeout = seconds;
+            self
+        }
+
+        pub fn default_port() -> u16 {
+            80
+        }
+    }
+}
+
+pub mod fetcher {
+    use mock_derive::mock;
+
+    #[mock]
+    use super::http::HttpClient;
+
+    pub fn fetch_status(client: &HttpClient) -> u16 {
+        client.get("/status").status
+    }
+
+    pub fn connect(base: &str) -> HttpClient {
+        HttpClient::new(base)
+    }
+}
+
+#[cfg(test)]
+use http::{MockHttpClient, MockHttpClientControl};
+
+#[test]
+fn mock_inherent_methods() {
+    let mut mock = MockHttpClient::new("https://example.com");
+    let method = mock.method_get()
+        .called_once()
+        .set_result(http::Response { status: 503 });
+
+    mock.set_get(method);
+    assert!(fetcher::fetch_status(&mock) == 503);
+}
+
+#[test]
+fn mock_inherent_constructor() {
+    let mut client = fetcher::connect("https://example.com");
+    let method = client.method_set_timeout()
+        .called_once()
+        .set_result(());
+
+    client.set_set_timeout(method);
+    client.set_timeout(5);
+    assert!(MockHttpClient::RETRIES == 3);
+}
+
+#[test]
+fn mock_inherent_getter_and_setter() {
+    let mut client = MockHttpClient::new("https://example.com");
+    let timeout = client.method_timeout()
+        .set_result(5);
+    let set_timeout = client.method_set_timeout()
+        .called_once()
+        .set_result(());
+
+    // The setter of the mock is shadowed by the method of the impl.
+    MockHttpClientControl::set_timeout(&mut client, timeout);
+    client.set_set_timeout(set_timeout);
+    client.set_timeout(10);
+    assert!(client.timeout() == 5);
+}
+
+#[test]
+fn mock_inherent_static_method() {
+    let method = MockHttpClientControl::method_default_port()
+        .set_result(8080);
+
+    MockHttpClientControl::set_default_port(method);
+    assert!(MockHttpClient::default_port() == 8080);
+}
+
+#[test]
+fn mock_inherent_builder() {
+    let mut client = MockHttpClient::new("https://example.com");
+    let with_timeout = client.method_with_timeout()
+        .called_once();
+    let timeout = client.method_timeout()
+        .set_result(5);
+
+    client.set_with_timeout(with_timeout);
+    MockHttpClientControl::set_timeout(&mut client, timeout);
+    // Without a result, the builder method passes the mock on.
+    let client = client.with_timeout(5);
+    assert!(client.timeout() == 5);
+}
+
+#[test]
+fn mock_inherent_builder_result() {
+    let mut client = MockHttpClient::new("https://example.com");
+    let with_timeout = client.method_with_timeout()
+        .return_result_of(|| {
+            let mut other = MockHttpClient::new("https://example.org");
+            let timeout = other.method_timeout()
+                .set_result(7);
+            MockHttpClientControl::set_timeout(&mut other, timeout);
+            other
+        });
+
+    client.set_with_timeout(with_timeout);
+    assert!(client.with_timeout(7).timeout() == 7);
+}
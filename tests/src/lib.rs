@@ -32,6 +32,23 @@ mod generics;
 mod foriegn_functions;
 mod foriegn_functions_mod2;
 mod advanced_traits;
+mod lifetimes;
+mod async_traits;
+mod sequences;
+mod std_traits;
+mod external_traits;
+mod gated_mocks;
+mod mock_naming;
+mod inherent_impls;
+mod functions;
+mod modules;
+mod foreign_handles;
+mod mock_storage;
+mod mock_scopes;
+mod poisoned_mocks;
+mod recorded_failures;
+mod callbacks;
+mod preload;
 
 #[allow(unused_imports)]
 use export::ExportTrait;
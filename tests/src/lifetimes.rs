@@ -0,0 +1,143 @@
+/*
+MIT License
+
+Copyright (c) 2020 David DeSimone
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+
+use mock_derive::mock;
+
+#[mock]
+trait Dictionary {
+    fn name(&self) -> &str;
+    fn entry<'s>(&'s self, key: &str) -> &'s String;
+    fn find<'a>(&self, key: &'a str) -> Option<&'a str>;
+    fn longest<'a>(&self, x: &'a str, y: &'a str) -> &'a str;
+    fn get(&self, key: &str) -> &str;
+}
+
+#[test]
+fn borrow_from_self() {
+    let mut mock = MockDictionary::new();
+    let method = mock.method_name()
+        .return_result_of(|| "Ferris".into());
+
+    mock.set_name(method);
+    assert!(mock.name() == "Ferris");
+    assert!(mock.name() == "Ferris");
+}
+
+#[test]
+#[should_panic(expected = "more than 65536 results borrowed from its mock")]
+fn borrow_from_self_retained_limit() {
+    let mut mock = MockDictionary::new();
+    let method = mock.method_name()
+        .return_result_of(|| "Ferris".into());
+
+    mock.set_name(method);
+    for _ in 0..=65536 {
+        mock.name();
+    }
+}
+
+#[test]
+fn borrow_from_self_named_lifetime() {
+    let mut mock = MockDictionary::new();
+    let method = mock.method_entry()
+        .called_once()
+        .set_result(Box::new(String::from("value")));
+
+    mock.set_entry(method);
+    assert!(mock.entry("key") == "value");
+}
+
+#[test]
+fn borrow_from_self_with_borrowed_argument() {
+    let mut mock = MockDictionary::new();
+    let method = mock.method_get()
+        .return_result_of(|| "value".into());
+
+    mock.set_get(method);
+    let key = String::from("key");
+    assert!(mock.get(&key) == "value");
+}
+
+#[test]
+#[should_panic(expected = "does not name an argument")]
+fn borrow_from_self_returns_arg() {
+    let mut mock = MockDictionary::new();
+    let method = mock.method_get()
+        .returns_arg(0);
+
+    mock.set_get(method);
+    mock.get("key");
+}
+
+#[test]
+fn method_lifetime_static_result() {
+    let mut mock = MockDictionary::new();
+    let method = mock.method_find()
+        .first_call()
+        .set_result(Some("found"))
+        .second_call()
+        .set_result(None);
+
+    mock.set_find(method);
+    let key = String::from("key");
+    assert!(mock.find(&key) == Some("found"));
+    assert!(mock.find(&key) == None);
+}
+
+#[test]
+fn returns_arg() {
+    let mut mock = MockDictionary::new();
+    let method = mock.method_find()
+        .returns_arg(0);
+
+    mock.set_find(method);
+    let key = String::from("not static");
+    assert!(mock.find(&key) == Some("not static"));
+}
+
+#[test]
+fn returns_arg_with_results() {
+    let mut mock = MockDictionary::new();
+    let method = mock.method_longest()
+        .first_call()
+        .set_result("static")
+        .returns_arg(1);
+
+    mock.set_longest(method);
+    let (x, y) = (String::from("x"), String::from("y"));
+    assert!(mock.longest(&x, &y) == "static");
+    assert!(mock.longest(&x, &y) == "y");
+}
+
+#[test]
+#[should_panic(expected = "does not name an argument")]
+fn returns_arg_out_of_range() {
+    let mut mock = MockDictionary::new();
+    let method = mock.method_longest()
+        .returns_arg(2);
+
+    mock.set_longest(method);
+    mock.longest("x", "y");
+}
@@ -0,0 +1,130 @@
+/*
+MIT License
+
+Copyright (c) 2020 David DeSimone
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+
+use mock_derive::mock;
+
+#[mock(name = "FakeClock")]
+trait WallClock {
+    fn now(&self) -> u64;
+}
+
+#[mock]
+trait Mailer {
+    fn send(&self, to: &str) -> bool;
+}
+
+#[mock]
+trait Notifier : Mailer {
+    fn notify(&self) -> usize;
+}
+
+#[mock]
+fn fetch_quota(user: &str) -> usize {
+    user.len()
+}
+
+#[mock(name = "FakeLedger", vis = "pub(crate)")]
+pub trait Ledger {
+    fn balance(&self) -> i64;
+}
+
+#[mock(name = "FakeMath")]
+extern "C" {
+    fn named_square(x: i32) -> i32;
+}
+
+// Mocks can be gathered in a module of their own with `pub use`.
+#[cfg(test)]
+mod doubles {
+    pub(super) use super::{MockMailer, MockNotifier, MockFetchQuota};
+}
+
+#[cfg(test)]
+mod fakes {
+    pub(crate) use super::{FakeLedger, FakeMath};
+}
+
+#[test]
+fn renamed_mock() {
+    let mut mock = FakeClock::new();
+    let method = mock.method_now()
+        .set_result(100);
+
+    mock.set_now(method);
+    assert!(mock.now() == 100);
+}
+
+#[test]
+fn mock_in_module() {
+    let mut mock = doubles::MockMailer::new();
+    let method = mock.method_send()
+        .called_once()
+        .set_result(true);
+
+    mock.set_send(method);
+    assert!(mock.send("ferris@example.com"));
+}
+
+#[test]
+fn derived_mock_in_module() {
+    let mut mock = doubles::MockNotifier::new();
+    let send = mock.method_send()
+        .set_result(false);
+    let notify = mock.method_notify()
+        .set_result(3);
+
+    mock.set_send(send);
+    mock.set_notify(notify);
+    assert!(!mock.send("ferris@example.com"));
+    assert!(mock.notify() == 3);
+}
+
+#[test]
+fn renamed_mock_in_module() {
+    let mut mock = fakes::FakeLedger::new();
+    let method = mock.method_balance()
+        .set_result(-5);
+
+    mock.set_balance(method);
+    assert!(mock.balance() == -5);
+}
+
+#[test]
+fn renamed_extern_mock() {
+    let mock = fakes::FakeMath::method_named_square()
+        .set_result(4);
+
+    fakes::FakeMath::set_named_square(mock);
+    unsafe { assert!(named_square(2) == 4); }
+}
+
+#[test]
+fn function_mock_in_module() {
+    let mock = doubles::MockFetchQuota::method_fetch_quota()
+        .set_result(10);
+
+    doubles::MockFetchQuota::set_fetch_quota(mock);
+    assert!(fetch_quota("ferris") == 10);
+}
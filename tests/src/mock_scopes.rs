@@ -0,0 +1,143 @@
+/*
+MIT License
+
+Copyright (c) 2020 David DeSimone
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+
+use mock_derive::mock;
+#[cfg(test)]
+use std::panic;
+
+#[allow(dead_code)]
+#[mock(name = "ExternSensor")]
+extern "C" {
+    fn sensor_read(channel: i32) -> i32;
+    fn sensor_reset();
+}
+
+#[allow(dead_code)]
+#[mock]
+trait Factory {
+    fn build(size: usize) -> Vec<u8>;
+}
+
+#[allow(dead_code)]
+#[mock]
+fn temperature() -> i32 {
+    20
+}
+
+#[allow(dead_code)]
+fn read(channel: i32) -> i32 {
+    unsafe { sensor_read(channel) }
+}
+
+#[test]
+fn scoped_extern_restores_previous() {
+    let method = ExternSensor::method_sensor_read()
+        .set_result(1);
+    ExternSensor::set_sensor_read(method);
+
+    {
+        let _guard = ExternSensor::scoped_sensor_read(ExternSensor::method_sensor_read()
+            .called_once()
+            .set_result(2));
+        assert!(read(0) == 2);
+    }
+
+    assert!(read(0) == 1);
+}
+
+#[test]
+#[should_panic(expected = "called at least")]
+fn scoped_extern_verifies_on_drop() {
+    let _guard = ExternSensor::scoped_sensor_read(ExternSensor::method_sensor_read()
+        .called_once()
+        .set_result(2));
+}
+
+#[test]
+fn scope_restores_whole_controller() {
+    let method = ExternSensor::method_sensor_read()
+        .set_result(1);
+    ExternSensor::set_sensor_read(method);
+
+    {
+        let _guard = ExternSensor::scope();
+        let method = ExternSensor::method_sensor_reset()
+            .called_once()
+            .set_result(());
+        ExternSensor::set_sensor_reset(method);
+        let method = ExternSensor::method_sensor_read()
+            .set_result(3);
+        ExternSensor::set_sensor_read(method);
+
+        unsafe { sensor_reset() };
+        assert!(read(0) == 3);
+    }
+
+    assert!(read(0) == 1);
+}
+
+#[test]
+fn scope_restores_while_unwinding() {
+    let method = ExternSensor::method_sensor_read()
+        .set_result(1);
+    ExternSensor::set_sensor_read(method);
+
+    let result = panic::catch_unwind(|| {
+        let _guard = ExternSensor::scoped_sensor_read(ExternSensor::method_sensor_read()
+            .called_ntimes(2)
+            .set_result(4));
+        assert!(read(0) == 4);
+        panic!("Failed while the scope was active");
+    });
+
+    assert!(result.is_err());
+    assert!(read(0) == 1);
+}
+
+#[test]
+fn scoped_static_method() {
+    {
+        let _guard = MockFactory::scoped_build(MockFactory::method_build()
+            .called_once()
+            .set_result(vec![1, 2]));
+        assert!(MockFactory::build(2) == vec![1, 2]);
+    }
+
+    let _guard = MockFactory::scope_statics();
+    MockFactory::set_build(MockFactory::method_build()
+        .set_result(Vec::new()));
+    assert!(MockFactory::build(0).is_empty());
+}
+
+#[test]
+fn scoped_function() {
+    {
+        let _guard = MockTemperature::scoped_temperature(MockTemperature::method_temperature()
+            .set_result(-5));
+        assert!(temperature() == -5);
+    }
+
+    assert!(temperature() == 20);
+}
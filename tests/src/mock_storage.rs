@@ -0,0 +1,149 @@
+/*
+MIT License
+
+Copyright (c) 2020 David DeSimone
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+
+use mock_derive::mock;
+use std::thread;
+
+#[mock(storage = "global")]
+fn next_id() -> u64 {
+    0
+}
+
+#[allow(dead_code)]
+#[mock]
+fn local_id() -> u64 {
+    0
+}
+
+#[allow(dead_code)]
+#[mock(name = "ExternWorker", storage = "global")]
+extern "C" {
+    fn worker_job_count() -> i32;
+    fn worker_open() -> *mut u8;
+}
+
+#[allow(dead_code)]
+#[mock(storage = "global")]
+trait Registry {
+    fn lookup(key: u32) -> String;
+}
+
+#[allow(dead_code)]
+fn next_id_in_worker() -> u64 {
+    thread::spawn(next_id).join().unwrap()
+}
+
+#[test]
+fn global_function_seen_by_worker() {
+    let _lock = MockNextId::lock();
+    let method = MockNextId::method_next_id()
+        .called_ntimes(2)
+        .return_result_of(|| 7);
+
+    MockNextId::set_next_id(method);
+    assert!(next_id_in_worker() == 7);
+    assert!(next_id() == 7);
+}
+
+#[test]
+fn lock_resets_global_function() {
+    {
+        let _lock = MockNextId::lock();
+        let method = MockNextId::method_next_id()
+            .set_result(3);
+        MockNextId::set_next_id(method);
+    }
+
+    let _lock = MockNextId::lock();
+    assert!(next_id_in_worker() == 0);
+}
+
+#[test]
+fn thread_local_function_hidden_from_worker() {
+    let method = MockLocalId::method_local_id()
+        .set_result(9);
+
+    MockLocalId::set_local_id(method);
+    assert!(thread::spawn(local_id).join().unwrap() == 0);
+    assert!(local_id() == 9);
+}
+
+#[test]
+fn global_extern_seen_by_worker() {
+    let _lock = ExternWorker::lock();
+    let handle: *mut u8 = ExternWorker::new_handle();
+    let count = ExternWorker::method_worker_job_count()
+        .set_result(4);
+    let open = ExternWorker::method_worker_open()
+        .set_result(handle);
+
+    ExternWorker::set_worker_job_count(count);
+    ExternWorker::set_worker_open(open);
+    let address = thread::spawn(|| unsafe { worker_open() as usize }).join().unwrap();
+    assert!(address == handle as usize);
+    assert!(thread::spawn(|| unsafe { worker_job_count() }).join().unwrap() == 4);
+    assert!(ExternWorker::open_handles() == 1);
+}
+
+#[test]
+fn lock_closes_global_handles() {
+    {
+        let _lock = ExternWorker::lock();
+        let _handle: *mut u8 = ExternWorker::new_handle();
+    }
+
+    let _lock = ExternWorker::lock();
+    ExternWorker::verify_handles();
+}
+
+#[test]
+fn global_static_method_seen_by_worker() {
+    let _lock = MockRegistry::lock_statics();
+    let method = MockRegistry::method_lookup()
+        .return_result_of(|| String::from("found"));
+
+    MockRegistry::set_lookup(method);
+    let value = thread::spawn(|| MockRegistry::lookup(1)).join().unwrap();
+    assert!(value == "found");
+}
+
+#[test]
+fn global_function_cleared_by_worker() {
+    let _lock = MockNextId::lock();
+    let worker = thread::spawn(|| {
+        for _ in 0..10_000 {
+            MockNextId::set_next_id(MockNextId::method_next_id()
+                .return_result_of(|| 7));
+            MockNextId::clear_next_id();
+        }
+    });
+
+    // Calls made while the worker clears the mock fall back to the original function.
+    while !worker.is_finished() {
+        let id = next_id();
+        assert!(id == 0 || id == 7);
+    }
+    worker.join().unwrap();
+}
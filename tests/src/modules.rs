@@ -0,0 +1,81 @@
+/*
+MIT License
+
+Copyright (c) 2020 David DeSimone
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+
+use mock_derive::mock;
+
+#[mock]
+pub mod fs_ops {
+    #[derive(Clone, PartialEq, Debug)]
+    pub struct Entry {
+        pub path: String,
+        pub size: usize,
+    }
+
+    pub fn read(path: &str) -> Option<Entry> {
+        Some(Entry { path: path.to_string(), size: 0 })
+    }
+
+    pub fn write(path: &str, data: &[u8]) -> bool {
+        !path.is_empty() && !data.is_empty()
+    }
+
+    // Generic functions can't be mocked, so they are left as they are.
+    #[mock(unmocked)]
+    pub fn first<T: Clone>(items: &[T]) -> Option<T> {
+        items.first().cloned()
+    }
+}
+
+pub fn backup(path: &str) -> bool {
+    match fs_ops::read(path) {
+        Some(entry) => fs_ops::write(&format!("{}.bak", entry.path), &vec![0; entry.size]),
+        None => false,
+    }
+}
+
+#[test]
+fn mock_module_functions() {
+    let read = MockFsOps::method_read()
+        .called_once()
+        .set_result(Some(fs_ops::Entry { path: String::from("a.txt"), size: 3 }));
+    let write = MockFsOps::method_write()
+        .called_once()
+        .set_result(true);
+
+    MockFsOps::set_read(read);
+    MockFsOps::set_write(write);
+    assert!(backup("a.txt"));
+}
+
+#[test]
+fn module_functions_use_original_bodies() {
+    let read = MockFsOps::method_read()
+        .set_result(None);
+
+    MockFsOps::set_read(read);
+    assert!(!backup("a.txt"));
+    assert!(fs_ops::write("a.txt", b"data"));
+    assert!(fs_ops::first(&[1, 2]) == Some(1));
+}
@@ -0,0 +1,105 @@
+/*
+MIT License
+
+Copyright (c) 2020 David DeSimone
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+
+use mock_derive::mock;
+#[cfg(test)]
+use std::panic::{self, AssertUnwindSafe};
+
+// Panics can't unwind out of extern "C" functions, so this block uses the Rust ABI.
+#[allow(dead_code)]
+#[mock(name = "ExternCounter")]
+extern "Rust" {
+    fn counter_next() -> i32;
+}
+
+#[allow(dead_code)]
+#[mock]
+trait Counter {
+    fn next(&self) -> i32;
+    fn reset() -> i32;
+}
+
+#[allow(dead_code)]
+#[mock(storage = "global")]
+fn shared_counter() -> i32 {
+    0
+}
+
+#[test]
+fn extern_mock_usable_after_failed_call() {
+    let method = ExternCounter::method_counter_next()
+        .called_at_most(1)
+        .set_result(1);
+    ExternCounter::set_counter_next(method);
+
+    assert!(unsafe { counter_next() } == 1);
+    assert!(panic::catch_unwind(|| unsafe { counter_next() }).is_err());
+
+    let method = ExternCounter::method_counter_next()
+        .set_result(2);
+    ExternCounter::set_counter_next(method);
+    assert!(unsafe { counter_next() } == 2);
+}
+
+#[test]
+fn trait_mock_usable_after_panicking_lambda() {
+    let mut mock = MockCounter::new();
+    let mut calls = 0;
+    let method = mock.method_next()
+        .return_result_of(move || {
+            calls += 1;
+            if calls == 1 {
+                panic!("Failed inside the lambda");
+            }
+            calls
+        });
+    mock.set_next(method);
+
+    assert!(panic::catch_unwind(AssertUnwindSafe(|| mock.next())).is_err());
+    assert!(mock.next() == 2);
+}
+
+#[test]
+fn static_method_usable_after_failed_call() {
+    let method = MockCounter::method_reset()
+        .never_called();
+    MockCounter::set_reset(method);
+    assert!(panic::catch_unwind(MockCounter::reset).is_err());
+
+    MockCounter::set_reset(MockCounter::method_reset().set_result(0));
+    assert!(MockCounter::reset() == 0);
+}
+
+#[test]
+fn global_mock_usable_after_failed_call() {
+    let _lock = MockSharedCounter::lock();
+    let method = MockSharedCounter::method_shared_counter()
+        .never_called();
+    MockSharedCounter::set_shared_counter(method);
+    assert!(std::thread::spawn(shared_counter).join().is_err());
+
+    MockSharedCounter::set_shared_counter(MockSharedCounter::method_shared_counter().set_result(4));
+    assert!(shared_counter() == 4);
+}
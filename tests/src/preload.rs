@@ -0,0 +1,90 @@
+/*
+MIT License
+
+Copyright (c) 2020 David DeSimone
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+// Builds the library of preload_fixture, and runs a C program with it preloaded in front
+// of libc. Both are built by the test, with the cargo and C compiler of the machine.
+#![cfg(target_os = "linux")]
+
+#[cfg(test)]
+use std::{env, fs};
+#[cfg(test)]
+use std::path::{Path, PathBuf};
+#[cfg(test)]
+use std::process::Command;
+
+#[cfg(test)]
+fn run(command: &mut Command) -> String {
+    let output = command.output().unwrap_or_else(|err| panic!("Can't run {:?}: {}", command, err));
+    assert!(output.status.success(), "{:?} failed:\n{}", command, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[cfg(test)]
+fn build_fixture(dir: &Path) -> (PathBuf, PathBuf) {
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("preload_fixture");
+    let target = Path::new(env!("CARGO_MANIFEST_DIR")).join("target").join("preload_fixture");
+    let cargo = env::var("CARGO").unwrap_or_else(|_| String::from("cargo"));
+    run(Command::new(cargo)
+        .arg("build")
+        .arg("--offline")
+        .arg("--features").arg("preload")
+        .arg("--manifest-path").arg(fixture.join("Cargo.toml"))
+        .arg("--target-dir").arg(&target));
+
+    // Builtins would be computed by the compiler rather than called.
+    let program = dir.join("program");
+    run(Command::new("cc")
+        .arg("-fno-builtin")
+        .arg(fixture.join("program.c"))
+        .arg("-o").arg(&program));
+    (target.join("debug").join("libpreload_fixture.so"), program)
+}
+
+#[test]
+fn preloaded_library_follows_scenario() {
+    let dir = env::temp_dir().join(format!("mock_derive_preload_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (library, program) = build_fixture(&dir);
+    let scenario = dir.join("scenario");
+    let log = dir.join("calls.log");
+    fs::write(&scenario, "# The first call fails\n\
+                          rand = -1\n\
+                          rand = 3\n\
+                          abs = 42\n\
+                          strerror = 0x10\n\
+                          drand48 = not a number\n").unwrap();
+
+    let stdout = run(Command::new(&program)
+        .env("LD_PRELOAD", &library)
+        .env("FIXTURE_SCENARIO", &scenario)
+        .env("FIXTURE_RESULTS", "drand48 = 0.5; drand48 = 1.5")
+        .env("FIXTURE_CALL_LOG", &log));
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), ["-1 3 3", "42", "0x10", "0.0 0.5"]);
+
+    let calls = fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = calls.lines().collect();
+    assert_eq!(lines[..4], ["srand(7)", "rand() -> -1", "rand() -> 3", "rand() -> 3"]);
+    assert_eq!(lines[4..6], ["abs(-3) -> 42", "strerror(2) -> 0x10"]);
+    assert_eq!(lines[6..], ["drand48 failed: Can't parse the result 'not a number' of drand48", "drand48() -> 0.5"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
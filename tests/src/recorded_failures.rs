@@ -0,0 +1,103 @@
+/*
+MIT License
+
+Copyright (c) 2020 David DeSimone
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+
+use mock_derive::mock;
+#[cfg(test)]
+use std::panic;
+
+#[allow(dead_code)]
+#[mock(name = "ExternCallbacks", failures = "record")]
+extern "C" {
+    fn on_event(code: i32) -> i32;
+    fn on_buffer(size: usize) -> *mut u8;
+    fn on_close();
+}
+
+// Stands in for a C library, which calls back into the mocks through function pointers.
+#[allow(dead_code)]
+unsafe fn dispatch(callback: unsafe extern "C" fn(i32) -> i32, code: i32) -> i32 {
+    callback(code)
+}
+
+#[test]
+fn failed_call_returns_default() {
+    let method = ExternCallbacks::method_on_event()
+        .never_called();
+    ExternCallbacks::set_on_event(method);
+
+    assert!(unsafe { dispatch(on_event, 1) } == 0);
+    assert!(panic::catch_unwind(ExternCallbacks::verify).is_err());
+    ExternCallbacks::verify();
+}
+
+#[test]
+fn failed_call_returns_fallback() {
+    let method = ExternCallbacks::method_on_event()
+        .called_at_most(1)
+        .set_result(5)
+        .fallback(-1);
+    ExternCallbacks::set_on_event(method);
+
+    assert!(unsafe { dispatch(on_event, 1) } == 5);
+    assert!(unsafe { dispatch(on_event, 2) } == -1);
+    assert!(panic::catch_unwind(ExternCallbacks::verify).is_err());
+}
+
+#[test]
+fn failed_pointer_call_returns_null() {
+    assert!(unsafe { on_buffer(16) }.is_null());
+    unsafe { on_close() };
+    assert!(panic::catch_unwind(ExternCallbacks::verify).is_err());
+}
+
+#[test]
+#[should_panic(expected = "on_event: Called a method that has been marked as 'never called'!")]
+fn verify_raises_recorded_failure() {
+    let method = ExternCallbacks::method_on_event()
+        .never_called();
+    ExternCallbacks::set_on_event(method);
+
+    unsafe { dispatch(on_event, 1) };
+    ExternCallbacks::verify();
+}
+
+#[test]
+#[should_panic(expected = "Extern mocks recorded failures")]
+fn scoped_guard_raises_recorded_failure() {
+    let _guard = ExternCallbacks::scoped_on_event(ExternCallbacks::method_on_event()
+        .never_called());
+    unsafe { dispatch(on_event, 1) };
+}
+
+#[test]
+fn scope_raises_recorded_failure() {
+    let result = panic::catch_unwind(|| {
+        let _guard = ExternCallbacks::scope();
+        unsafe { on_close() };
+    });
+
+    assert!(result.is_err());
+    ExternCallbacks::verify();
+}
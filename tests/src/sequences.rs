@@ -0,0 +1,156 @@
+/*
+MIT License
+
+Copyright (c) 2020 David DeSimone
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+
+use mock_derive::mock;
+
+use std::ops::DerefMut;
+use std::pin::Pin;
+#[cfg(test)]
+use std::sync::Arc;
+use std::task::{Context, Poll};
+#[cfg(test)]
+use std::task::{Wake, Waker};
+
+// The Stream trait from the futures crate.
+pub trait Stream {
+    type Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>>;
+}
+
+impl<P> Stream for Pin<P> where P: DerefMut + Unpin, P::Target: Stream {
+    type Item = <P::Target as Stream>::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().as_mut().poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+struct NoopWaker;
+
+#[cfg(test)]
+impl Wake for NoopWaker {
+    fn wake(self: Arc<Self>) {}
+}
+
+// Polls 'stream' until it ends, recording pending points as None.
+#[cfg(test)]
+fn collect_steps<S: Stream + Unpin>(mut stream: S) -> Vec<Option<S::Item>> {
+    let waker = Waker::from(Arc::new(NoopWaker));
+    let mut cx = Context::from_waker(&waker);
+    let mut steps = Vec::new();
+    loop {
+        match Pin::new(&mut stream).poll_next(&mut cx) {
+            Poll::Ready(Some(item)) => steps.push(Some(item)),
+            Poll::Ready(None) => return steps,
+            Poll::Pending => steps.push(None),
+        }
+    }
+}
+
+#[mock]
+trait Catalog {
+    fn names(&self) -> Box<dyn Iterator<Item = String>>;
+    fn ids(&self) -> impl Iterator<Item = u32> + Send;
+    fn rows(&self) -> Box<dyn Iterator<Item = Result<u32, String>>>;
+}
+
+#[mock]
+trait Feed {
+    fn events(&self) -> Pin<Box<dyn Stream<Item = u32> + Send>>;
+    fn updates(&mut self) -> impl Stream<Item = Result<u32, String>>;
+}
+
+#[mock]
+trait Source<T> {
+    fn items(&self) -> Box<dyn Iterator<Item = T>>;
+}
+
+#[test]
+fn yields_boxed_iterator() {
+    let mut mock = MockCatalog::new();
+    let method = mock.method_names()
+        .first_call()
+        .yields(vec![String::from("a"), String::from("b")])
+        .second_call()
+        .yields(vec![]);
+
+    mock.set_names(method);
+    assert!(mock.names().collect::<Vec<_>>() == vec!["a", "b"]);
+    assert!(mock.names().next().is_none());
+}
+
+#[test]
+fn yields_impl_iterator() {
+    let mut mock = MockCatalog::new();
+    let method = mock.method_ids()
+        .yields_steps(vec![Some(1), None, Some(2)]);
+
+    mock.set_ids(method);
+    assert!(mock.ids().sum::<u32>() == 3);
+}
+
+#[test]
+fn yields_then_err_iterator() {
+    let mut mock = MockCatalog::new();
+    let method = mock.method_rows()
+        .yields_then_err(vec![1, 2], String::from("disconnected"));
+
+    mock.set_rows(method);
+    let rows: Vec<_> = mock.rows().collect();
+    assert!(rows == vec![Ok(1), Ok(2), Err(String::from("disconnected"))]);
+}
+
+#[test]
+fn yields_boxed_stream() {
+    let mut mock = MockFeed::new();
+    let method = mock.method_events()
+        .yields_steps(vec![Some(1), None, Some(2)]);
+
+    mock.set_events(method);
+    assert!(collect_steps(mock.events()) == vec![Some(1), None, Some(2)]);
+}
+
+#[test]
+fn yields_impl_stream() {
+    let mut mock = MockFeed::new();
+    let method = mock.method_updates()
+        .yields_steps(vec![Some(Ok(1)), None, Some(Err(String::from("timeout"))), Some(Ok(2))]);
+
+    mock.set_updates(method);
+    let steps = collect_steps(mock.updates());
+    assert!(steps == vec![Some(Ok(1)), None, Some(Err(String::from("timeout"))), Some(Ok(2))]);
+}
+
+#[test]
+fn yields_generic_items() {
+    let mut mock = MockSource::<char>::new();
+    let method = mock.method_items()
+        .yields(vec!['x', 'y']);
+
+    mock.set_items(method);
+    assert!(mock.items().collect::<String>() == "xy");
+}
@@ -0,0 +1,187 @@
+/*
+MIT License
+
+Copyright (c) 2020 David DeSimone
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+
+THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+SOFTWARE.
+*/
+
+
+use mock_derive::mock;
+
+use std::fmt::Display;
+#[cfg(test)]
+use std::io::{Read, Write};
+
+#[mock]
+trait Cursor : Iterator<Item = u32> {
+    fn offset(&self) -> usize;
+}
+
+#[mock]
+trait Channel : std::io::Read + std::io::Write {
+    fn name(&self) -> String;
+}
+
+#[mock]
+trait Token : Clone + PartialEq {
+    fn id(&self) -> u32;
+}
+
+#[mock]
+trait Label : Display {
+    fn width(&self) -> usize;
+}
+
+// Traits that share their names with std traits are still mocked as the user's own.
+mod store {
+    use mock_derive::mock;
+
+    #[mock]
+    pub trait Write {
+        fn put(&mut self, key: &str, value: u32);
+    }
+}
+
+mod io {
+    use mock_derive::mock;
+
+    #[mock]
+    pub trait Read {
+        fn get(&self, key: &str) -> Option<u32>;
+    }
+}
+
+#[mock]
+trait Cache : store::Write + io::Read {
+    fn hits(&self) -> usize;
+}
+
+mod journal {
+    use mock_derive::mock;
+    use super::store::Write;
+
+    #[mock]
+    pub trait Journal : self::Write {
+        fn entries(&self) -> usize;
+    }
+}
+
+#[test]
+fn mock_iterator() {
+    let mut mock = MockCursor::new();
+    let next = mock.method_next()
+        .first_call()
+        .set_result(Some(4))
+        .second_call()
+        .set_result(Some(5))
+        .nth_call(3)
+        .set_result(None);
+    let offset = mock.method_offset()
+        .set_result(2);
+
+    mock.set_next(next);
+    mock.set_offset(offset);
+    assert!(mock.offset() == 2);
+    assert!(mock.collect::<Vec<_>>() == vec![4, 5]);
+}
+
+#[test]
+fn mock_read_write() {
+    let mut mock = MockChannel::new();
+    let write = mock.method_write()
+        .returns_arg(0)
+        .return_result_of(|| Ok(3));
+    let flush = mock.method_flush()
+        .called_once()
+        .set_result(Ok(()));
+    let read = mock.method_read()
+        .set_result(Err(std::io::Error::new(std::io::ErrorKind::Other, "closed")));
+
+    mock.set_write(write);
+    mock.set_flush(flush);
+    mock.set_read(read);
+    assert!(mock.write(b"abc").unwrap() == 3);
+    mock.flush().unwrap();
+    assert!(mock.read(&mut [0; 4]).is_err());
+}
+
+#[test]
+fn mock_clone_partial_eq() {
+    let mut mock = MockToken::new();
+    let clone = mock.method_clone()
+        .called_once()
+        .return_result_of(|| {
+            let mut copy = MockToken::new();
+            let id = copy.method_id()
+                .set_result(7);
+            copy.set_id(id);
+            copy
+        });
+    let eq = mock.method_eq()
+        .set_result(true);
+
+    mock.set_clone(clone);
+    mock.set_eq(eq);
+    let copy = mock.clone();
+    assert!(copy.id() == 7);
+    assert!(mock == copy);
+}
+
+#[test]
+fn mock_user_traits_named_like_std() {
+    let mut mock = MockCache::new();
+    let put = mock.method_put()
+        .called_once()
+        .set_result(());
+    let get = mock.method_get()
+        .set_result(Some(3));
+
+    mock.set_put(put);
+    mock.set_get(get);
+    store::Write::put(&mut mock, "key", 3);
+    assert!(io::Read::get(&mock, "key") == Some(3));
+
+    let mut journal = journal::MockJournal::new();
+    let put = journal.method_put()
+        .called_once()
+        .set_result(());
+    journal.set_put(put);
+    store::Write::put(&mut journal, "key", 3);
+}
+
+#[test]
+fn mock_display() {
+    let mock = MockLabel::new();
+    mock.set_print_string(String::from("label"));
+    assert!(format!("{:>6}", mock) == " label");
+}
+
+#[test]
+fn mock_display_method() {
+    let mut mock = MockLabel::new();
+    let fmt = mock.method_fmt()
+        .first_call()
+        .set_result(String::from("first"));
+    mock.set_fmt(fmt);
+    mock.set_print_string(String::from("label"));
+
+    assert!(mock.to_string() == "first");
+    assert!(mock.to_string() == "label");
+}